@@ -1,11 +1,25 @@
 use super::util::Coord;
-use super::tetris::Tetris;
+use super::tetris::{Tetris, Delays};
 use super::block::Block;
-use super::scoreboard::Scoreboard;
-use super::timer::TickTimer;
-use super::enums::{ShiftCmd, RotateCmd, BlockID, GameStatus};
+use super::timer::{TickTimer, FrameClock, START_PERIOD, MIN_PERIOD, LVL_UP, FRAME_RATE, MAX_CATCH_UP_MS};
+use super::bot::Bot;
+use super::finesse::Finesse;
+use super::events::{self, GameEvent, GameObserver};
+use super::stats::{GameStats, SessionStats};
+use super::save::SaveGame;
+use super::replay::{Replay, ReplayAction, Playback};
+use super::options::Options;
+use super::theme::Theme;
+use super::pieces::{Piece, PieceSet};
+use super::rotation::RotationSystem;
+use super::audio::{Audio, AudioSettings};
+use super::input::{Controls, Handling, TimeUnit};
+use super::fumen::{self, FumenPage, FumenError};
+use super::enums::{ShiftCmd, RotateCmd, GameCmd, BlockID, GameMode, GameStatus, TopOut, Phase};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use eframe;
 use egui::{self, Key, Color32};
 
 // ------------------------------------------------------------------------------------------------
@@ -13,58 +27,396 @@ use egui::{self, Key, Color32};
 
 pub const CELL_SIZE: f32 = 30.0;
 pub const SIDEPANEL_WIDTH: f32 = 250.0;
+// Rows of the hidden buffer above the board that are shown (dimmed), so blocks sticking out stay visible
+pub const BUFFER_PEEK: f32 = 0.5;
 
-const STROKE_WIDTH: f32 = 2.0;
-const ROUNDING_PX: f32 = 2.0;
-const STROKE: egui::Stroke      = egui::Stroke { width: STROKE_WIDTH, color: Color32::BLACK };
-const ROUNDING: egui::Rounding  = egui::Rounding { nw: ROUNDING_PX, ne: ROUNDING_PX, sw: ROUNDING_PX, se: ROUNDING_PX, };
 const CELL: egui::Rect          = egui::Rect { min: egui::pos2(0.0, 0.0), max: egui::pos2(CELL_SIZE, CELL_SIZE) };
+const BOARD_TOP: f32            = BUFFER_PEEK * CELL_SIZE;
+const BUFFER_SHADE: f32         = 0.6;
+const GLYPH_SIZE: f32           = 0.5 * CELL_SIZE;
+const FULL_UV: egui::Rect       = egui::Rect { min: egui::pos2(0.0, 0.0), max: egui::pos2(1.0, 1.0) };
+
+const SAVE_KEY: &str = "save_game";
+const THEME_KEY: &str = "theme";
+const GLYPHS_KEY: &str = "glyphs";
+const AUDIO_KEY: &str = "audio";
+const HANDLING_KEY: &str = "handling";
+const ROTATIONS_KEY: &str = "rotations";
+
+const HISTORY_LEN: usize = 100;
+
+// Keys of the game commands, held shift and soft drop keys repeat (see `Handling`)
+const BINDINGS: [(Key, GameCmd); 12] = [
+    (Key::Space, GameCmd::HardDrop),
+    (Key::S, GameCmd::SoftDrop),
+    (Key::C, GameCmd::Hold),
+    (Key::H, GameCmd::Shift(ShiftCmd::Left)),
+    (Key::ArrowLeft, GameCmd::Shift(ShiftCmd::Left)),
+    (Key::L, GameCmd::Shift(ShiftCmd::Right)),
+    (Key::ArrowRight, GameCmd::Shift(ShiftCmd::Right)),
+    (Key::K, GameCmd::Rotate(RotateCmd::Right)),
+    (Key::ArrowUp, GameCmd::Rotate(RotateCmd::Right)),
+    (Key::J, GameCmd::Rotate(RotateCmd::Left)),
+    (Key::ArrowDown, GameCmd::Rotate(RotateCmd::Left)),
+    (Key::A, GameCmd::Rotate(RotateCmd::Half)),
+];
+const MAX_HANDLING_MS: i64 = 500;
+
+// Apps on the same page (web) are numbered, and the keyboard goes to the one that was clicked last
+static NEXT_INSTANCE: AtomicUsize = AtomicUsize::new(0);
+static FOCUSED_INSTANCE: AtomicUsize = AtomicUsize::new(0);
 
-const COLOR_WALL: Color32 = Color32::WHITE;
-const COLOR_I: Color32 = Color32::from_rgb(200, 150, 150);
-const COLOR_J: Color32 = Color32::from_rgb(150, 200, 150);
-const COLOR_L: Color32 = Color32::from_rgb(150, 150, 200);
-const COLOR_O: Color32 = Color32::from_rgb(200, 200, 150);
-const COLOR_S: Color32 = Color32::from_rgb(200, 150, 200);
-const COLOR_T: Color32 = Color32::from_rgb(150, 200, 200);
-const COLOR_Z: Color32 = Color32::from_rgb(200, 200, 200);
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_DIR: &str = "replays";
 
-const START_PERIOD: i64 = 1024;
-const MIN_PERIOD: i64 = 32;
-const LVL_UP: i64 = 500;
+const BOT_PERIOD: i64 = 100;
+const HINT_OPACITY: f32 = 0.3;
+
+const DELAYS: Delays = Delays { entry_ms: 100, line_clear_ms: 300 };
+const MAX_DELAY_MS: i64 = 1000;
+const LOCK_FLASH_MS: i64 = 150;
+const COLOR_FLASH: Color32 = Color32::WHITE;
 
 // ------------------------------------------------------------------------------------------------
 // Main application
 
 pub struct TetrisApp {
-    // Score of the tetris game
-    scoreboard: Scoreboard,
-
     // How often the game should tick (in milliseconds, ms)
     timer: TickTimer,
 
+    // Logical frames the game steps in, however often the screen is drawn
+    clock: FrameClock,
+
     // State of the board
     game: Tetris,
 
-    // Game size
+    // Rule set of the game
+    mode: GameMode,
+
+    // Pieces dealt in new games, and the sets to choose from
+    pieces: PieceSet,
+    piece_sets: Vec<PieceSet>,
+
+    // Rotation system picked for each mode, SRS for the ones that were never changed
+    rotations: HashMap<GameMode, RotationSystem>,
+
+    // Sandbox tools: color to paint with (None erases), queue being typed, and whether gravity is on
+    paint_id: Option<BlockID>,
+    queue_input: String,
+    gravity: bool,
+
+    // Bot used for both playing and hinting, and how often it may issue a command
+    bot: Bot,
+    bot_timer: TickTimer,
+    bot_target: Option<Vec<Coord>>,
+    watch_ai: bool,
+    show_hint: bool,
+
+    // Finesse trainer, judges the inputs of every block and keeps a summary for the session
+    finesse: Finesse,
+    show_finesse: bool,
+
+    // Colors and shapes used for painting, the themes to choose from, and the texture of the skin
+    theme: Theme,
+    themes: Vec<Theme>,
+    skin: Option<egui::TextureHandle>,
+    theme_status: String,
+
+    // Draw a glyph on every cell, so blocks can be told apart without relying on color
+    glyphs: bool,
+
+    // Sound effects for the events of the game, and the music
+    audio: Audio,
+
+    // Keys of the game commands that are down and how they repeat, and the handling set by the player
+    controls: Controls,
+    handling: Handling,
+
+    // Delays for new games, and the cells of the last locked block while they flash
+    delays: Delays,
+    lock_flash: Vec<Coord>,
+    lock_flash_ms: i64,
+
+    // Statistics of the current game, and of the games before it in this session
+    stats: GameStats,
+    session: SessionStats,
+    stats_status: String,
+
+    // Game saved at the last shutdown, kept until the user decides whether to resume it
+    resume: Option<SaveGame>,
+
+    // Fumen string typed into the import box, and the outcome of the last import/export
+    fumen_input: String,
+    fumen_status: String,
+
+    // Recording of the current game, dropped as soon as the game is edited or something is undone,
+    // and the replay being played back instead of playing
+    recording: Option<Replay>,
+    playback: Option<Playback>,
+    replay_status: String,
+
+    // Observers added from outside the app, e.g., by the web page, by id
+    listeners: Vec<(u32, Box<dyn GameObserver>)>,
+    next_listener: u32,
+
+    // Neither time nor input reach the game while paused
+    paused: bool,
+
+    // Number of this app among the ones running, and the name its settings are stored under
+    instance: usize,
+    name: String,
+
+    // Game size, seed (random if not set) and level of new games
     width: i32,
     height: i32,
+    seed: Option<u64>,
+    start_level: usize,
 }
 
 impl TetrisApp {
 
-    /// todo!()
-    pub fn new(cc: &eframe::CreationContext<'_>, width: i32, height: i32) -> Self {
+    /// The app as set up by the options, a replay in the options is played back right away
+    pub fn new(cc: &eframe::CreationContext<'_>, options: Options) -> Self {
+        Self::new_instance(cc, options, "")
+    }
+
+    /// Like `new`, for one of several apps (e.g., canvases on a page) that store their settings under their own `name`
+    pub fn new_instance(cc: &eframe::CreationContext<'_>, options: Options, name: &str) -> Self {
+        let (width, height) = options.board_size();
 
         // Setup font of context
         setup_context(&cc.egui_ctx);
 
         // Creates resources
         let timer       = TickTimer::new(START_PERIOD, MIN_PERIOD, LVL_UP);
-        let scoreboard  = Scoreboard::new();
-        let game        = Tetris::new(width, height);
+        let game        = Tetris::new(width, height, random_seed());
+        let bot_timer   = TickTimer::new(BOT_PERIOD, BOT_PERIOD, LVL_UP);
+
+        // Look for a game in progress from the last time the app was closed
+        let resume = cc.storage
+            .and_then(|storage| eframe::get_value::<Option<SaveGame>>(storage, &storage_key(name, SAVE_KEY)))
+            .flatten()
+            .filter(|save| save.is_compatible()
+                && save.game.width() == width
+                && save.game.height() == height)
+            .filter(|_| options.replay.is_none());
+
+        let theme = options.theme.clone()
+            .or_else(|| cc.storage.and_then(|storage| eframe::get_value::<Theme>(storage, &storage_key(name, THEME_KEY))))
+            .unwrap_or_default();
+        let glyphs = cc.storage
+            .and_then(|storage| eframe::get_value::<bool>(storage, &storage_key(name, GLYPHS_KEY)))
+            .unwrap_or(false);
+        let audio_settings = cc.storage
+            .and_then(|storage| eframe::get_value::<AudioSettings>(storage, &storage_key(name, AUDIO_KEY)))
+            .unwrap_or_default();
+        let handling = cc.storage
+            .and_then(|storage| eframe::get_value::<Handling>(storage, &storage_key(name, HANDLING_KEY)))
+            .unwrap_or_default();
+        let mut rotations = cc.storage
+            .and_then(|storage| eframe::get_value::<HashMap<GameMode, RotationSystem>>(storage, &storage_key(name, ROTATIONS_KEY)))
+            .unwrap_or_default();
+        if let Some(rotation) = options.rotation {
+            rotations.insert(options.mode, rotation);
+        }
+
+        let mut app = Self {
+            timer,
+            clock: FrameClock::new(FRAME_RATE),
+            game,
+            mode: options.mode,
+            pieces: options.pieces.clone(),
+            piece_sets: PieceSet::available(),
+            rotations,
+            paint_id: Some(BlockID::G),
+            queue_input: String::new(),
+            gravity: true,
+            bot: Bot::default(),
+            bot_timer,
+            bot_target: None,
+            watch_ai: false,
+            show_hint: false,
+            finesse: Finesse::new(),
+            show_finesse: false,
+            theme: Theme::default(),
+            themes: Theme::available(),
+            skin: None,
+            theme_status: String::new(),
+            glyphs,
+            audio: Audio::new(audio_settings),
+            controls: Controls::new(handling),
+            handling,
+            delays: DELAYS,
+            lock_flash: vec![],
+            lock_flash_ms: 0,
+            stats: GameStats::new(),
+            session: SessionStats::new(),
+            stats_status: String::new(),
+            resume,
+            fumen_input: String::new(),
+            fumen_status: String::new(),
+            recording: None,
+            playback: None,
+            replay_status: String::new(),
+            listeners: vec![],
+            next_listener: 0,
+            paused: false,
+            instance: NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed),
+            name: name.to_string(),
+            width,
+            height,
+            seed: options.seed,
+            start_level: options.level,
+        };
+        app.set_theme(&cc.egui_ctx, theme);
+        match options.replay {
+            Some(replay) => app.start_playback(replay),
+            None => app.reset(),
+        }
+        app
+    }
+
+    /// The settings new games are started with
+    pub fn options(&self) -> Options {
+        Options {
+            width: self.width,
+            height: self.height,
+            seed: self.seed,
+            mode: self.mode,
+            level: self.start_level,
+            pieces: self.pieces.clone(),
+            rotation: Some(self.rotation()),
+            theme: Some(self.theme.clone()),
+            ..Options::default()
+        }
+    }
+
+    /// Rotation system of new games in the current mode, NES games always turn like the NES
+    pub fn rotation(&self) -> RotationSystem {
+        match self.mode {
+            GameMode::Nes => RotationSystem::Nes,
+            _ => self.rotations.get(&self.mode).copied().unwrap_or_default(),
+        }
+    }
+
+    // Key repeat of the current mode, NES games use the timing of the NES
+    fn handling(&self) -> Handling {
+        match self.mode {
+            GameMode::Nes => Handling::NES,
+            _ => self.handling,
+        }
+    }
+
+    // Repeat held keys as the current mode does, keys held in another unit of time have to be pressed again
+    fn apply_handling(&mut self) {
+        let handling = self.handling();
+        if handling.unit != self.controls.handling.unit {
+            self.controls.release_all();
+        }
+        self.controls.handling = handling;
+    }
+
+    // Game time in the unit the key repeat counts in
+    fn input_time(&self) -> i64 {
+        match self.controls.handling.unit {
+            TimeUnit::Milliseconds => self.clock.time_ms(),
+            TimeUnit::Frames => self.clock.frames(),
+        }
+    }
+
+    pub fn score(&self) -> usize {
+        self.game.score()
+    }
+
+    pub fn level(&self) -> usize {
+        self.game.level()
+    }
+
+    /// Lines cleared in the current game
+    pub fn lines(&self) -> usize {
+        self.stats.lines()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops (or continues) the game, the gravity starts over after a pause
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            self.timer.reset_tick();
+        }
+        self.paused = paused;
+    }
+
+    /// Whether keyboard input goes to this app
+    pub fn has_focus(&self) -> bool {
+        FOCUSED_INSTANCE.load(Ordering::Relaxed) == self.instance
+    }
+
+    /// Sends keyboard input to this app instead of the others
+    pub fn focus(&self) {
+        FOCUSED_INSTANCE.store(self.instance, Ordering::Relaxed);
+    }
+
+    /// Lets `observer` follow the events of every game from now on, returns the id to unsubscribe with
+    pub fn subscribe(&mut self, observer: Box<dyn GameObserver>) -> u32 {
+        let id = self.next_listener;
+        self.next_listener += 1;
+        self.listeners.push((id, observer));
+        id
+    }
+
+    /// Returns whether there was an observer with the id
+    pub fn unsubscribe(&mut self, id: u32) -> bool {
+        let len = self.listeners.len();
+        self.listeners.retain(|(listener, _)| *listener != id);
+        self.listeners.len() < len
+    }
+
+    /// Starts a new game with the settings (or plays back the replay in them)
+    pub fn set_options(&mut self, ctx: &egui::Context, options: Options) {
+        (self.width, self.height) = options.board_size();
+        self.seed = options.seed;
+        self.mode = options.mode;
+        self.start_level = options.level;
+        self.pieces = options.pieces;
+        if let Some(rotation) = options.rotation {
+            self.rotations.insert(options.mode, rotation);
+        }
+        self.resume = None;
+        if let Some(theme) = options.theme {
+            self.set_theme(ctx, theme);
+        }
+        match options.replay {
+            Some(replay) => self.start_playback(replay),
+            None => self.reset(),
+        }
+    }
+
+    // Switch to `theme` and upload its skin, a theme whose skin fails to load is painted without it
+    fn set_theme(&mut self, ctx: &egui::Context, theme: Theme) {
+        self.skin = match theme.load_skin() {
+            Ok(image) => {
+                self.theme_status.clear();
+                image.map(|image| ctx.load_texture("skin", image, egui::TextureOptions::LINEAR))
+            },
+            Err(e) => {
+                self.theme_status = e.to_string();
+                None
+            },
+        };
+        self.theme = theme;
+    }
 
-        Self { scoreboard, timer, game, width, height }
+    // What is needed to paint cells in the current theme
+    fn look(&self) -> Look<'_> {
+        Look {
+            theme: &self.theme,
+            pieces: self.game.pieces(),
+            skin: self.skin.as_ref().map(|skin| skin.id()),
+            glyphs: self.glyphs,
+        }
     }
 
     /// todo!()
@@ -77,57 +429,648 @@ impl TetrisApp {
         }
 
         if ctx.input(|i| i.key_pressed(Key::R)) { self.reset(); }
+        if ctx.input(|i| i.key_pressed(Key::P)) { self.set_paused(!self.paused); }
+
+        // Undo/Redo
+        if ctx.input(|i| i.modifiers.command && !i.modifiers.shift && i.key_pressed(Key::Z)) { self.undo(); }
+        if ctx.input(|i| i.modifiers.command && (i.key_pressed(Key::Y) || (i.modifiers.shift && i.key_pressed(Key::Z)))) { self.redo(); }
     }
 
     /// todo!()
     /// Handles user input that affect the tetris game state
     fn handle_user_input_game(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // User Commands
-        if ctx.input(|i| i.key_pressed(Key::Space)) {
-            if let Some(num_lines_cleared) = self.game.hard_drop() {
-                self.update_score_and_tickrate(num_lines_cleared);
+        // Every press and release in the order they happened, they take effect at the current game time
+        let (events, keys_down) = ctx.input(|i| (i.events.clone(), i.keys_down.clone()));
+        let time = self.input_time();
+
+        // Keys released while another window had the keyboard never send their release
+        let released: Vec<GameCmd> = self.controls.held()
+            .filter(|cmd| !BINDINGS.iter().any(|(key, bound)| bound == cmd && keys_down.contains(key)))
+            .collect();
+        for cmd in released {
+            self.controls.key(cmd, false, time);
+        }
+
+        for event in events {
+            let egui::Event::Key { key, pressed, repeat: false, modifiers } = event else { continue };
+            // Shortcuts like `Ctrl + Z` are not game input
+            if modifiers.command && pressed {
+                continue
+            }
+            for (_, cmd) in BINDINGS.iter().filter(|(bound, _)| *bound == key) {
+                if let Some(cmd) = self.controls.key(*cmd, pressed, time) {
+                    self.execute_input(&cmd);
+                }
             }
         }
-        if ctx.input(|i| i.key_pressed(Key::H) || i.key_pressed(Key::ArrowLeft))    { self.game.shift_block_if_feasible(&ShiftCmd::Left); }
-        if ctx.input(|i| i.key_pressed(Key::L) || i.key_pressed(Key::ArrowRight))   { self.game.shift_block_if_feasible(&ShiftCmd::Right); }
-        if ctx.input(|i| i.key_pressed(Key::K) || i.key_pressed(Key::ArrowUp))      { self.game.rotate_block_if_feasible(&RotateCmd::Left); }
-        if ctx.input(|i| i.key_pressed(Key::J) || i.key_pressed(Key::ArrowDown))    { self.game.rotate_block_if_feasible(&RotateCmd::Right); }
     }
 
-    /// todo!()
-    fn tick(&mut self) {
-        if self.timer.get_time_until_tick() <= 0 {
-            if let Some(num_lines_cleared) = self.game.tick() {
-                self.update_score_and_tickrate(num_lines_cleared);
+    // Repeat the held keys that are due by the current game time
+    fn handle_held_keys(&mut self) {
+        // A command repeated by a held key is not another input of the player
+        for (_, cmd) in self.controls.repeats(self.input_time()) {
+            self.execute(&cmd);
+        }
+
+        // Rotation and hold keys held down while no block falls are given to the block that spawns next (IRS/IHS)
+        if *self.game.phase() != Phase::Falling {
+            let hold = self.controls.is_held(&GameCmd::Hold).then_some(GameCmd::Hold);
+            let rotate = self.controls.held().find(|cmd| matches!(cmd, GameCmd::Rotate(_)));
+            for cmd in [hold, rotate].into_iter().flatten() {
+                if !self.game.initial_input().contains(&cmd) {
+                    self.execute_input(&cmd);
+                }
+            }
+        }
+    }
+
+    // Execute a command on the game, no matter if it came from the user, the bot or a replay
+    fn execute(&mut self, cmd: &GameCmd) {
+        self.record_action(ReplayAction::Cmd(*cmd));
+        self.game.execute(cmd);
+        self.dispatch_events();
+    }
+
+    // A key pressed by the player, counted for the statistics and judged by the finesse trainer
+    fn execute_input(&mut self, cmd: &GameCmd) {
+        self.finesse.input(cmd);
+        self.stats.add_key();
+        self.execute(cmd);
+    }
+
+    // Advance the game by one logical frame
+    fn step(&mut self, step_ms: i64) {
+        if self.playback.is_some() {
+            self.play_back(step_ms);
+            return
+        }
+
+        // Alter tetris state based on the bot or the keys held by the user
+        if self.watch_ai {
+            self.bot_step(step_ms);
+        } else {
+            self.handle_held_keys();
+        }
+
+        self.update_phase(step_ms);
+        self.tick(step_ms);
+        self.stats.add_time(step_ms);
+    }
+
+    // Let time pass in the delays and animations
+    fn update_phase(&mut self, elapsed_ms: i64) {
+        self.lock_flash_ms -= elapsed_ms;
+        if *self.game.phase() != Phase::Falling {
+            self.record_action(ReplayAction::Update(elapsed_ms));
+        }
+        self.game.update(elapsed_ms);
+        self.dispatch_events();
+    }
+
+    // React to what just happened in the game, right away so the observers see the board it happened on
+    fn dispatch_events(&mut self) {
+        let events = self.game.drain_events();
+        for event in &events {
+            match event {
+                GameEvent::Locked { cells, .. } => {
+                    self.update_tickrate();
+                    self.lock_flash = cells.clone();
+                    self.lock_flash_ms = LOCK_FLASH_MS;
+                },
+                // Without a line clear delay the rows are gone already, so there is nothing left to flash
+                GameEvent::LinesCleared { rows, .. } if !rows.is_empty() && !matches!(self.game.phase(), Phase::LineClear { .. }) => {
+                    self.lock_flash_ms = 0;
+                },
+                _ => (),
+            }
+        }
+
+        let mut observers: Vec<&mut dyn GameObserver> = vec![&mut self.stats, &mut self.audio];
+        // Only the player's own blocks are judged
+        if self.show_finesse && !self.watch_ai && self.playback.is_none() {
+            observers.push(&mut self.finesse);
+        }
+        observers.extend(self.listeners.iter_mut().map(|(_, listener)| listener.as_mut() as &mut dyn GameObserver));
+        events::dispatch(&events, &self.game, &mut observers);
+    }
+
+    // Count the inputs of the current block from its current position
+    fn restart_finesse(&mut self) {
+        if self.show_finesse {
+            self.finesse.spawn(&self.game);
+        }
+    }
+
+    // How many placements and edits can be taken back, ranked modes allow no undo at all
+    fn undo_limit(&self) -> Option<usize> {
+        (!self.mode.is_ranked()).then_some(HISTORY_LEN)
+    }
+
+    // Start a fresh history for the current game
+    fn reset_history(&mut self) {
+        self.game.set_undo_limit(self.undo_limit());
+    }
+
+    // Remember the current state before it is edited
+    fn record(&mut self) {
+        self.game.record_edit();
+        self.recording = None;
+    }
+
+    fn undo(&mut self) {
+        if self.playback.is_none() && self.game.undo() {
+            self.recording = None;
+            self.bot_target = None;
+            self.restart_finesse();
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.playback.is_none() && self.game.redo() {
+            self.recording = None;
+            self.bot_target = None;
+            self.restart_finesse();
+        }
+    }
+
+    // Switch rule set, only the sandbox keeps the current board (its block spawns again if the rules
+    // or the rotation system change)
+    fn set_mode(&mut self, mode: GameMode) {
+        self.mode = mode;
+        if mode != GameMode::Sandbox {
+            self.gravity = true;
+            self.reset();
+        } else {
+            if self.game.rules() != mode.ruleset() {
+                self.game.set_rules(mode.ruleset());
+            }
+            if self.game.rotation() != self.rotation() {
+                self.game.set_rotation(self.rotation());
+            }
+            self.apply_handling();
+            self.reset_history();
+            // The game goes on under other rules, so its recording could not be played back
+            self.recording = None;
+        }
+    }
+
+    // Paint (primary button) or erase (secondary button) the cells under the pointer
+    fn edit_board(&mut self, response: &egui::Response) {
+        let erase = response.secondary_clicked() || response.dragged_by(egui::PointerButton::Secondary);
+        let paint = response.clicked() || response.dragged_by(egui::PointerButton::Primary);
+        if !(paint || erase) {
+            return
+        }
+
+        // One undo step per click or stroke
+        if response.drag_started() || response.clicked() || response.secondary_clicked() {
+            self.record();
+        }
+
+        if let Some(pos) = response.interact_pointer_pos() {
+            let coord = Coord((pos.x / CELL_SIZE).floor() as i32, ((pos.y - BOARD_TOP) / CELL_SIZE).floor() as i32);
+            let inside = (1..=self.width).contains(&coord.0) && (0..self.height).contains(&coord.1);
+            if inside && !self.game.current_block().config().any(|&c| c == coord) {
+                self.game.set_cell(coord, if erase { None } else { self.paint_id });
+                self.restart_finesse();
+            }
+        }
+    }
+
+    // Tools for setting up a position by hand
+    fn show_sandbox_tools(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Sandbox")
+            .resizable(false)
+            .default_pos(egui::pos2(CELL_SIZE * (self.width as f32 + 2.5), CELL_SIZE))
+            .show(ctx, |ui| {
+                ui.label("Paint (right click erases):");
+                ui.horizontal_wrapped(|ui| {
+                    for (id, piece) in self.game.pieces().ids().zip(&self.game.pieces().pieces) {
+                        ui.selectable_value(&mut self.paint_id, Some(id), &piece.name);
+                    }
+                    ui.selectable_value(&mut self.paint_id, Some(BlockID::G), "G");
+                    ui.selectable_value(&mut self.paint_id, None, "Erase");
+                });
+
+                ui.label("Current block:");
+                ui.horizontal_wrapped(|ui| {
+                    let pieces: Vec<(BlockID, String)> = self.game.pieces().ids()
+                        .zip(self.game.pieces().pieces.iter().map(|piece| piece.name.clone()))
+                        .collect();
+                    for (id, name) in pieces {
+                        if ui.button(name).clicked() {
+                            self.record();
+                            self.game.spawn_current_block(id);
+                            self.restart_finesse();
+                        }
+                    }
+                });
+
+                ui.label("Queue:");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.queue_input);
+                    if ui.button("Set").clicked() {
+                        let queue = self.game.pieces().parse_queue(&self.queue_input);
+                        self.record();
+                        self.game.set_queue(&queue);
+                    }
+                });
+
+                ui.checkbox(&mut self.gravity, "Gravity");
+            });
+    }
+
+    // Let the bot issue its next command towards the placement it is currently aiming for
+    fn bot_step(&mut self, elapsed_ms: i64) {
+        // The bot plans for the falling block, anything it did in the delays would carry over to the next one
+        if *self.game.phase() != Phase::Falling {
+            return
+        }
+        self.bot_timer.advance(elapsed_ms);
+        if !self.bot_timer.take_tick() {
+            return
+        }
+
+        if let Some(placement) = self.bot.plan(&self.game, self.bot_target.as_deref()) {
+            self.bot_target = Some(placement.cells());
+            if let Some(cmd) = placement.cmds.first() {
+                self.execute(cmd);
+            }
+        }
+    }
+
+    // Let the gravity act on the time that passed, as many times as it is due
+    fn tick(&mut self, elapsed_ms: i64) {
+        self.timer.advance_frame(elapsed_ms);
+        while self.timer.take_tick() {
+            if self.gravity {
+                self.record_action(ReplayAction::Tick);
+                self.game.tick();
+                self.dispatch_events();
             }
-            self.timer.reset_tick();
         }
     }
 
     /// todo!()
     fn reset(&mut self) {
         // Creates resources
-        self.timer      = TickTimer::new(START_PERIOD, MIN_PERIOD, LVL_UP);
-        self.scoreboard = Scoreboard::new();
-        self.game       = Tetris::new(self.width, self.height);
+        let seed        = self.seed.unwrap_or_else(random_seed);
+        self.game       = self.new_game(seed);
+        self.start_level = self.game.start_level();
+        self.timer      = TickTimer::with_rules(self.game.rules(), self.game.start_level());
+        self.bot_target = None;
+        self.start_game();
+        self.recording  = Some(Replay::new(&self.game, seed, self.mode));
+    }
+
+    // A fresh game with the settings of the current mode
+    fn new_game(&self, seed: u64) -> Tetris {
+        let mut game = Tetris::with_pieces(self.width, self.height, seed, self.pieces.clone());
+        game.set_rules(self.mode.ruleset());
+        game.set_rotation(self.rotation());
+        game.set_start_level(self.start_level);
+        game
+    }
+
+    // Watch a recorded game instead of playing
+    fn start_playback(&mut self, replay: Replay) {
+        self.game       = replay.new_game();
+        self.timer      = TickTimer::with_rules(self.game.rules(), self.game.start_level());
+        self.mode       = replay.mode;
+        self.bot_target = None;
+        self.start_game();
+        self.game.set_delays(replay.delays);
+        self.replay_status = format!("Replaying {} actions", replay.len());
+        self.playback   = Some(Playback::new(replay));
+    }
+
+    // Apply the actions of the replay that are due, time passes as in the recorded game
+    fn play_back(&mut self, elapsed_ms: i64) {
+        self.lock_flash_ms -= elapsed_ms;
+        self.stats.add_time(elapsed_ms);
+        let Some(playback) = &mut self.playback else { return };
+        let actions = playback.advance(self.stats.time_ms);
+        if playback.is_finished() {
+            self.replay_status = String::from("Replay finished");
+        }
+        for action in actions {
+            match action {
+                ReplayAction::Cmd(cmd) => self.execute(&cmd),
+                ReplayAction::Tick => { self.game.tick(); },
+                ReplayAction::Update(ms) => { self.game.update(ms); },
+                ReplayAction::Delays(delays) => self.game.set_delays(delays),
+            }
+            self.dispatch_events();
+        }
+    }
+
+    fn record_action(&mut self, action: ReplayAction) {
+        if let Some(recording) = &mut self.recording {
+            recording.record(self.stats.time_ms, action);
+        }
+    }
+
+    // Write the recording of the current game to `replays/` (native) or copy it to the clipboard (web)
+    fn save_replay(&mut self, _ctx: &egui::Context) {
+        let Some(recording) = &self.recording else { return };
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = std::path::PathBuf::from(REPLAY_DIR)
+                .join(format!("replay-{}.json", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+            self.replay_status = match recording.save(&path) {
+                Ok(()) => format!("Saved {}", path.display()),
+                Err(e) => e.to_string(),
+            };
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            _ctx.output_mut(|o| o.copied_text = recording.to_json());
+            self.replay_status = String::from("Copied to clipboard");
+        }
+    }
+
+    // Save the recording of the current game, or stop the replay that is played back
+    fn show_replay_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        ui.horizontal(|ui| {
+            ui.label("Replay:");
+            if self.playback.is_some() {
+                if ui.button("Stop").clicked() {
+                    self.reset();
+                }
+            } else {
+                let recorded = self.recording.as_ref().is_some_and(|recording| !recording.is_empty());
+                if ui.add_enabled(recorded, egui::Button::new("Save")).clicked() {
+                    self.save_replay(ctx);
+                }
+            }
+        });
+        if self.playback.is_none() && self.recording.is_none() {
+            ui.small("Not recorded, the game was edited");
+        } else if !self.replay_status.is_empty() {
+            ui.small(&self.replay_status);
+        }
+    }
+
+    // Book keeping for a game that was just started, loaded or set up
+    fn start_game(&mut self) {
+        self.recording = None;
+        self.playback = None;
+        self.replay_status.clear();
+        self.session.add(std::mem::take(&mut self.stats));
+        self.stats.set_board(&self.game);
+        self.game.set_delays(self.delays);
+        self.lock_flash_ms = 0;
+        self.apply_handling();
+        self.reset_history();
+        self.restart_finesse();
+    }
+
+    // Continue a saved game
+    fn load(&mut self, save: SaveGame) {
+        self.game       = save.game;
+        self.timer      = save.timer;
+        self.timer.reset_tick();
+        self.mode       = save.mode;
+        self.pieces     = self.game.pieces().clone();
+        self.delays     = self.game.delays();
+        self.bot_target = None;
+        self.start_game();
+    }
+
+    fn show_pause_popup(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Paused")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if ui.button("Resume").clicked() {
+                    self.set_paused(false);
+                }
+            });
+    }
+
+    // Ask whether the saved game should be resumed or a new game started
+    fn show_resume_popup(&mut self, ctx: &egui::Context) {
+        let mut resume = None;
+        egui::Window::new("Resume game?")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if let Some(save) = &self.resume {
+                    ui.label(format!("Saved score: {} p", save.game.score()));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Resume").clicked()    { resume = Some(true); }
+                    if ui.button("New game").clicked()  { resume = Some(false); }
+                });
+            });
+
+        match resume {
+            Some(true) => if let Some(save) = self.resume.take() { self.load(save); },
+            Some(false) => { self.resume = None; self.reset(); },
+            None => {},
+        }
+    }
+
+    // Practice from the position in the first page of the fumen in the import box
+    fn import_fumen(&mut self) {
+        // The position is set up in a game of the current mode, so its rules, rotation and pieces stay
+        let mut game = self.new_game(self.seed.unwrap_or_else(random_seed));
+        let imported = fumen::decode(&self.fumen_input)
+            .and_then(|pages| pages.first().cloned().ok_or(FumenError::UnexpectedEnd))
+            .and_then(|page| page.apply_to(&mut game));
+
+        match imported {
+            Ok(()) => {
+                self.game       = game;
+                self.start_level = self.game.start_level();
+                self.timer      = TickTimer::with_rules(self.game.rules(), self.game.start_level());
+                self.bot_target = None;
+                self.start_game();
+                self.fumen_status = String::from("Imported");
+            },
+            Err(e) => self.fumen_status = e.to_string(),
+        }
+    }
+
+    // Copy the current position as a fumen to the clipboard
+    fn export_fumen(&mut self, ctx: &egui::Context) {
+        match FumenPage::from_game(&self.game) {
+            Ok(page) => {
+                let fumen = fumen::encode(&[page]);
+                ctx.output_mut(|o| o.copied_text = fumen);
+                self.fumen_status = String::from("Copied to clipboard");
+            },
+            Err(e) => self.fumen_status = e.to_string(),
+        }
+    }
+
+    // Live finesse of the current and last block, and the summary of the session
+    fn show_finesse_panel(&self, ui: &mut egui::Ui) {
+        ui.label(format!("Inputs: {}", self.finesse.inputs()));
+        if let Some(last) = self.finesse.last() {
+            let text = format!("Last block: {} inputs, {} needed", last.inputs, last.optimal);
+            if last.is_fault() {
+                ui.colored_label(Color32::RED, text);
+            } else {
+                ui.label(text);
+            }
+        }
+        let stats = self.finesse.stats();
+        ui.label(format!("Session: {} faults in {} blocks ({:.0}%)", stats.faults, stats.pieces, 100.0 * stats.accuracy()));
+        ui.label(format!("Extra inputs: {}", stats.extra_inputs));
+    }
+
+    // Pick one of the built-in themes or the ones found in `assets/themes/`
+    fn show_theme_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let mut selected = None;
+        egui::ComboBox::from_label("Theme")
+            .selected_text(&self.theme.name)
+            .show_ui(ui, |ui| {
+                for theme in &self.themes {
+                    if ui.selectable_label(*theme == self.theme, &theme.name).clicked() {
+                        selected = Some(theme.clone());
+                    }
+                }
+            });
+        if let Some(theme) = selected {
+            self.set_theme(ctx, theme);
+        }
+        if !self.theme_status.is_empty() {
+            ui.small(&self.theme_status);
+        }
+        ui.checkbox(&mut self.glyphs, "Piece glyphs");
+    }
+
+    // Entry and line clear delay, 0 for competitive play
+    fn show_delays_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Delays").show(ui, |ui| {
+            let entry = ui.add(egui::Slider::new(&mut self.delays.entry_ms, 0..=MAX_DELAY_MS).text("ARE (ms)"));
+            let line_clear = ui.add(egui::Slider::new(&mut self.delays.line_clear_ms, 0..=MAX_DELAY_MS).text("Line clear (ms)"));
+            if entry.changed() || line_clear.changed() {
+                self.record_action(ReplayAction::Delays(self.delays));
+                self.game.set_delays(self.delays);
+            }
+        });
+    }
+
+    // Delayed auto shift and auto repeat of held keys, fixed to the NES timing in NES games
+    fn show_handling_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Handling").show(ui, |ui| {
+            let nes = self.mode == GameMode::Nes;
+            if nes {
+                ui.small("NES games use the DAS of the NES");
+            }
+            ui.add_enabled_ui(!nes, |ui| {
+                let handling = &mut self.handling;
+                ui.add(egui::Slider::new(&mut handling.das, 0..=MAX_HANDLING_MS).text("DAS (ms)"));
+                ui.add(egui::Slider::new(&mut handling.arr, 0..=MAX_HANDLING_MS).text("ARR (ms)"));
+                ui.add(egui::Slider::new(&mut handling.soft_drop, 0..=MAX_HANDLING_MS).text("Soft drop (ms)"));
+            });
+            self.apply_handling();
+        });
+    }
+
+    // Volumes of the sound effects and the music
+    fn show_audio_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Audio").show(ui, |ui| {
+            if !self.audio.is_available() {
+                ui.small("No audio device");
+            }
+            let settings = &mut self.audio.settings;
+            ui.add(egui::Slider::new(&mut settings.volume, 0.0..=1.0).text("Effects"));
+            ui.add(egui::Slider::new(&mut settings.music_volume, 0.0..=1.0).text("Music"));
+            ui.checkbox(&mut settings.music, "Play music");
+        });
+    }
+
+    // Statistics of the current game and the session, with export to the clipboard
+    fn show_stats_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        egui::CollapsingHeader::new("Statistics").show(ui, |ui| {
+            stats_grid(ui, "game_stats", &self.stats);
+        });
+        egui::CollapsingHeader::new("Session").show(ui, |ui| {
+            ui.label(format!("Games: {}", self.session.games().len() + 1));
+            stats_grid(ui, "session_stats", &self.session.total(&self.stats));
+            ui.horizontal(|ui| {
+                let export = if ui.button("Copy CSV").clicked() {
+                    Some(self.session.to_csv(&self.stats))
+                } else if ui.button("Copy JSON").clicked() {
+                    Some(self.session.to_json(&self.stats))
+                } else {
+                    None
+                };
+                if let Some(text) = export {
+                    ctx.output_mut(|o| o.copied_text = text);
+                    self.stats_status = String::from("Copied to clipboard");
+                }
+            });
+            ui.small(&self.stats_status);
+        });
     }
 
-    // Send how many lines were cleared to the scoreboard
-    fn update_score_and_tickrate(&mut self, num_lines_cleared: usize) {
-        self.scoreboard.update_score(num_lines_cleared);
-        self.timer.update_period_from_score(self.scoreboard.get_score());
+    // Speed up the gravity as the score (or under NES rules, the level) grows
+    fn update_tickrate(&mut self) {
+        self.timer.update_period(self.game.score(), self.game.level());
     }
 
     // Paint the state config of the tetris game
     fn paint_state(&self, painter: &egui::Painter) {
         self.game.state_config()
-            .for_each(|block| paint_block(&painter, block));
+            .for_each(|block| paint_block(painter, &self.look(), block));
+    }
+
+    // Shade the part of the hidden buffer that is shown above the board
+    fn paint_buffer(&self, painter: &egui::Painter) {
+        let rect = egui::Rect::from_min_size(
+            egui::pos2(0.0, 0.0),
+            egui::vec2(CELL_SIZE * (self.width as f32 + 2.0), BOARD_TOP));
+        painter.rect_filled(rect, 0.0, self.theme.background.linear_multiply(BUFFER_SHADE));
     }
 
     // Paint the walls (boundary) of the tetris game
     fn paint_boundary(&self, painter: &egui::Painter) {
         self.game.boundary_config()
-            .for_each(|&coord| paint_coord(&painter, CELL_SIZE * coord, &COLOR_WALL));
+            .for_each(|&coord| paint_coord(painter, &self.look(), board_pos(coord), self.theme.wall, None));
+    }
+
+    // Flash the last locked block, and flash and then collapse the rows that are being cleared
+    fn paint_animations(&self, painter: &egui::Painter) {
+        if self.lock_flash_ms > 0 {
+            let color = COLOR_FLASH.linear_multiply(self.lock_flash_ms as f32 / LOCK_FLASH_MS as f32);
+            self.lock_flash.iter()
+                .for_each(|&coord| paint_coord(painter, &self.look(), board_pos(coord), color, None));
+        }
+
+        if let Phase::LineClear { rows, remaining_ms } = self.game.phase() {
+            let progress = 1.0 - *remaining_ms as f32 / self.game.delays().line_clear_ms.max(1) as f32;
+            for &row in rows {
+                let y = BOARD_TOP + CELL_SIZE * row as f32;
+                let row_rect = egui::Rect::from_min_size(
+                    egui::pos2(CELL_SIZE, y),
+                    egui::vec2(CELL_SIZE * self.width as f32, CELL_SIZE));
+                if progress < 0.5 {
+                    // Blink twice
+                    let blink = (progress * 4.0 * std::f32::consts::PI).sin().abs();
+                    painter.rect_filled(row_rect, self.theme.rounding(), COLOR_FLASH.linear_multiply(blink));
+                } else {
+                    // Shrink towards the middle of the row
+                    let height = CELL_SIZE * (2.0 * progress - 1.0).min(1.0);
+                    painter.rect_filled(
+                        egui::Rect::from_center_size(row_rect.center(), egui::vec2(row_rect.width(), height)),
+                        0.0,
+                        self.theme.background);
+                }
+            }
+        }
+    }
+
+    // Paint where the bot would place the current block
+    fn paint_hint(&self, painter: &egui::Painter) {
+        if let Some(placement) = self.bot.best_placement(&self.game) {
+            let color = self.look().color(placement.block.id()).linear_multiply(HINT_OPACITY);
+            placement.block.config()
+                .for_each(|&coord| paint_coord(painter, &self.look(), board_pos(coord), color, Some(placement.block.id())));
+        }
     }
 }
 
@@ -135,20 +1078,51 @@ impl TetrisApp {
 // Gui loop
 
 impl eframe::App for TetrisApp {
+    // Lets the web handle reach the app
+    #[cfg(target_arch = "wasm32")]
+    fn as_any_mut(&mut self) -> Option<&mut dyn std::any::Any> {
+        Some(self)
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
 
-        // Alter app state based on user input
-        self.handle_user_input_app(&ctx, _frame);
+        // Alter app state based on user input, unless the user is typing in a text box
+        if ctx.input(|i| i.pointer.any_pressed()) {
+            self.focus();
+        }
+        let typing = ctx.wants_keyboard_input() || !self.has_focus();
+        if typing {
+            self.controls.release_all();
+        }
+        if !typing {
+            self.handle_user_input_app(ctx, _frame);
+        }
 
-        // If we are still able to play
-        if let GameStatus::Okay = self.game.status() {
-            // Alter tetris state based on user input
-            self.handle_user_input_game(&ctx, _frame);
+        // If there is a saved game the user needs to decide what to do first,
+        // otherwise check if we are still able to play
+        if self.resume.is_some() {
+            self.show_resume_popup(ctx);
+        } else if self.paused {
+            self.show_pause_popup(ctx);
+        } else if let GameStatus::Okay = self.game.status() {
+            if self.playback.is_none() && !self.watch_ai && !typing {
+                self.handle_user_input_game(ctx, _frame);
+            }
 
-            // Update tick
-            self.tick();
+            // Catch the game up with the real time in fixed frames, the panels then draw the latest state
+            let elapsed_us = (ctx.input(|i| i.unstable_dt) as f64 * 1e6) as i64;
+            for _ in 0..self.clock.advance(elapsed_us) {
+                if self.game.status() != GameStatus::Okay || self.paused {
+                    break
+                }
+                let step_ms = self.clock.step();
+                self.step(step_ms);
+            }
         }
 
+        // Keep the music going
+        self.audio.update();
+
         // Update right hand side of gui
         egui::SidePanel::right("side_panel")
             .exact_width(SIDEPANEL_WIDTH)
@@ -157,42 +1131,184 @@ impl eframe::App for TetrisApp {
                 ui.vertical_centered(|ui| {
                     ui.label("");
                     ui.label("Score:");
-                    ui.label(format!("{} p", self.scoreboard.get_score()));
+                    ui.label(format!("{} p", self.game.score()));
                     ui.label("");
                     ui.separator();
                     ui.label("");
                     if let GameStatus::Okay = self.game.status() {
-                        paint_next_block(ui.painter(), &self.game.peek_next_block(), &ui.next_widget_position());
+                        paint_next_block(ui.painter(), &self.look(), &self.game.peek_next_block(), &ui.next_widget_position());
                     };
+                    ui.add_space(3.0 * CELL_SIZE);
+                    ui.separator();
+                    ui.label("Hold:");
+                    paint_next_block(ui.painter(), &self.look(), &self.game.held_block().cloned(), &ui.next_widget_position());
+                    ui.add_space(3.0 * CELL_SIZE);
+                    ui.separator();
+                    let mut mode = self.mode;
+                    egui::ComboBox::from_label("Mode")
+                        .selected_text(format!("{:?}", mode))
+                        .show_ui(ui, |ui| {
+                            for option in GameMode::ALL {
+                                ui.selectable_value(&mut mode, option, format!("{:?}", option));
+                            }
+                        });
+                    if mode != self.mode {
+                        self.set_mode(mode);
+                    }
+                    let mut selected = None;
+                    egui::ComboBox::from_label("Pieces")
+                        .selected_text(&self.pieces.name)
+                        .show_ui(ui, |ui| {
+                            for pieces in &self.piece_sets {
+                                // Sets with pieces wider than the board can't be played on it
+                                let fits = Tetris::check_size(self.width, self.height, pieces).is_ok();
+                                let label = egui::SelectableLabel::new(*pieces == self.pieces, &pieces.name);
+                                if ui.add_enabled(fits, label).clicked() {
+                                    selected = Some(pieces.clone());
+                                }
+                            }
+                        });
+                    if let Some(pieces) = selected {
+                        self.pieces = pieces;
+                        self.reset();
+                    }
+                    let mut rotation = self.rotation();
+                    ui.add_enabled_ui(self.mode != GameMode::Nes, |ui| {
+                        egui::ComboBox::from_label("Rotation")
+                            .selected_text(rotation.name())
+                            .show_ui(ui, |ui| {
+                                for option in RotationSystem::ALL {
+                                    ui.selectable_value(&mut rotation, option, option.name());
+                                }
+                            });
+                    });
+                    if rotation != self.rotation() {
+                        self.rotations.insert(self.mode, rotation);
+                        self.reset();
+                    }
+                    // The game starts over once the value is let go of
+                    let response = ui.add(egui::DragValue::new(&mut self.start_level)
+                        .clamp_range(self.mode.ruleset().start_levels())
+                        .prefix("Start level: "));
+                    if response.drag_released() || (response.changed() && !response.dragged()) {
+                        self.reset();
+                    }
+                    if self.undo_limit().is_some() {
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(self.game.can_undo(), egui::Button::new("Undo")).clicked() { self.undo(); }
+                            if ui.add_enabled(self.game.can_redo(), egui::Button::new("Redo")).clicked() { self.redo(); }
+                        });
+                    }
+                    ui.checkbox(&mut self.watch_ai, "Watch AI");
+                    ui.checkbox(&mut self.show_hint, "Show hint");
+                    if ui.checkbox(&mut self.show_finesse, "Finesse trainer").changed() {
+                        self.restart_finesse();
+                    }
+                    if self.show_finesse {
+                        self.show_finesse_panel(ui);
+                    }
+                    ui.separator();
+                    self.show_theme_panel(ui, ctx);
+                    self.show_delays_panel(ui);
+                    self.show_handling_panel(ui);
+                    self.show_audio_panel(ui);
+                    self.show_stats_panel(ui, ctx);
+                    self.show_replay_panel(ui, ctx);
+                    ui.separator();
+                    ui.label("Fumen:");
+                    ui.text_edit_singleline(&mut self.fumen_input);
+                    ui.horizontal(|ui| {
+                        if ui.button("Import").clicked() { self.import_fumen(); }
+                        if ui.button("Export").clicked() { self.export_fumen(ctx); }
+                    });
+                    ui.small(&self.fumen_status);
                 });
             });
 
         // Paint tetris field
         egui::CentralPanel::default()
+            .frame(egui::Frame::central_panel(&ctx.style()).fill(self.theme.background))
             .show(ctx, |ui| {
+                if self.mode == GameMode::Sandbox {
+                    let response = ui.interact(ui.max_rect(), ui.id().with("board"), egui::Sense::click_and_drag());
+                    self.edit_board(&response);
+                }
                 self.paint_boundary(ui.painter());
                 self.paint_state(ui.painter());
-                if let GameStatus::Okay = self.game.status() {
-                    paint_block(ui.painter(), &self.game.current_block());
+                self.paint_animations(ui.painter());
+                if let (GameStatus::Okay, Phase::Falling) = (self.game.status(), self.game.phase()) {
+                    if self.show_hint {
+                        self.paint_hint(ui.painter());
+                    }
+                    paint_block(ui.painter(), &self.look(), self.game.current_block());
                 };
+                self.paint_buffer(ui.painter());
             });
 
+        if self.mode == GameMode::Sandbox {
+            self.show_sandbox_tools(ctx);
+        }
+
         // If game is over print popup
         if let GameStatus::GameOver = self.game.status() {
             egui::Window::new("Game Over!")
                 .collapsible(false)
                 .resizable(false)
                 .show(ctx, |ui| {
-                    ui.label(format!("Final score: {} p", self.scoreboard.get_score()));
+                    ui.label(format!("Final score: {} p", self.game.score()));
+                    match self.game.top_out() {
+                        Some(TopOut::BlockOut) => { ui.label("Block out: no room for the next block"); },
+                        Some(TopOut::LockOut) => { ui.label("Lock out: locked above the board"); },
+                        None => (),
+                    }
+                    if self.show_finesse {
+                        let stats = self.finesse.stats();
+                        ui.label(format!("Finesse: {} faults in {} blocks this session", stats.faults, stats.pieces));
+                    }
                 });
         };
 
-        // Sleep until request repaint or repaint at once if there exists other repaint requests
-        let time = self.timer.get_time_until_tick();
-        ctx.request_repaint_after(
-            if time <= 0 { std::time::Duration::ZERO }
-            else { std::time::Duration::from_millis(time as u64) }
-        );
+        // Sleep until request repaint or repaint at once if there exists other repaint requests,
+        // never longer than the frame clock makes up for so no gravity time is lost
+        let mut time = self.timer.get_time_until_tick().min(MAX_CATCH_UP_MS / 2);
+        if *self.game.phase() != Phase::Falling || self.lock_flash_ms > 0 || self.playback.is_some() {
+            time = 0;
+        }
+        if self.watch_ai {
+            time = time.min(self.bot_timer.get_time_until_tick());
+        }
+        if let Some(repeat) = self.controls.next_repeat() {
+            let until = repeat - self.input_time();
+            time = time.min(match self.controls.handling.unit {
+                TimeUnit::Milliseconds => until,
+                TimeUnit::Frames => until * 1000 / FRAME_RATE,
+            });
+        }
+        // A paused game only changes on input
+        if !self.paused {
+            ctx.request_repaint_after(
+                if time <= 0 { std::time::Duration::ZERO }
+                else { std::time::Duration::from_millis(time as u64) }
+            );
+        }
+    }
+
+    // Autosave the game in progress, the native window or tab might be closed at any moment
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let save = match (&self.resume, self.game.status()) {
+            (Some(resume), _) => Some(resume.clone()),
+            (None, GameStatus::Okay) => Some(SaveGame::new(self.mode, self.game.clone(), self.timer.clone())),
+            (None, GameStatus::GameOver) => None,
+        };
+        // A replay is not a game of the player, whatever was saved before stays
+        if self.playback.is_none() {
+            eframe::set_value(storage, &storage_key(&self.name, SAVE_KEY), &save);
+        }
+        eframe::set_value(storage, &storage_key(&self.name, THEME_KEY), &self.theme);
+        eframe::set_value(storage, &storage_key(&self.name, GLYPHS_KEY), &self.glyphs);
+        eframe::set_value(storage, &storage_key(&self.name, AUDIO_KEY), &self.audio.settings);
+        eframe::set_value(storage, &storage_key(&self.name, HANDLING_KEY), &self.handling);
+        eframe::set_value(storage, &storage_key(&self.name, ROTATIONS_KEY), &self.rotations);
     }
 }
 
@@ -201,52 +1317,121 @@ impl eframe::App for TetrisApp {
 // Aux functions for drawing
 
 
+// The theme and the texture of its skin, and the pieces of the game, everything needed to paint a cell
+struct Look<'a> {
+    theme: &'a Theme,
+    pieces: &'a PieceSet,
+    skin: Option<egui::TextureId>,
+    glyphs: bool,
+}
+
+impl Look<'_> {
+    fn color(&self, id: &BlockID) -> Color32 {
+        self.theme.color(self.pieces, id)
+    }
+}
+
+// Screen position of a board cell, the board starts below the part of the buffer that is shown
+fn board_pos(coord: Coord) -> Coord {
+    CELL_SIZE * coord + Coord(0, BOARD_TOP as i32)
+}
+
 // Paint a block 
-fn paint_block(painter: &egui::Painter, block: &Block) {
+fn paint_block(painter: &egui::Painter, look: &Look<'_>, block: &Block) {
     block.config().for_each(|&coord| {
-        paint_coord(&painter, CELL_SIZE * coord, color_from_id(block.id()))
+        paint_coord(painter, look, board_pos(coord), look.color(block.id()), Some(block.id()))
+    });
+}
+
+// Two columns of statistics
+fn stats_grid(ui: &mut egui::Ui, id: &str, stats: &GameStats) {
+    let distribution = stats.distribution.iter()
+        .map(|(name, n)| format!("{}:{}", name, n))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let rows = [
+        ("Pieces", stats.pieces.to_string()),
+        ("PPS", format!("{:.2}", stats.pps())),
+        ("APM", format!("{:.1}", stats.apm())),
+        ("KPP", format!("{:.2}", stats.kpp())),
+        ("Lines", stats.lines().to_string()),
+        ("Single / Double", format!("{} / {}", stats.singles, stats.doubles)),
+        ("Triple / Tetris", format!("{} / {}", stats.triples, stats.tetrises)),
+        ("T-spin 0/1/2/3", format!("{}/{}/{}/{}", stats.tspins, stats.tspin_singles, stats.tspin_doubles, stats.tspin_triples)),
+        ("Max combo", stats.max_combo.to_string()),
+        ("Holes", stats.holes.to_string()),
+    ];
+    egui::Grid::new(id).show(ui, |ui| {
+        for (name, value) in rows {
+            ui.label(name);
+            ui.label(value);
+            ui.end_row();
+        }
     });
+    ui.small(distribution);
 }
 
 // paint the next block that is gonna appear
-fn paint_next_block(painter: &egui::Painter, block: &Option<Block>, at_pos: &egui::Pos2) {
+fn paint_next_block(painter: &egui::Painter, look: &Look<'_>, block: &Option<Block>, at_pos: &egui::Pos2) {
     if let Some(block) = block {
         let half_block_width = CELL_SIZE * (block.width() as f32) / 2.0;
         block.config().for_each(|&coord| {
             paint_coord(
-                &painter,
+                painter,
+                look,
                 Coord(
                     (at_pos.x - half_block_width + CELL_SIZE * coord.0 as f32) as i32,
                     (at_pos.y + CELL_SIZE * (coord.1 + 1) as f32) as i32
                 ),
-                color_from_id(block.id()));
+                look.color(block.id()),
+                Some(block.id()));
         });
     }
 }
 
-// Paint one cell
-fn paint_coord(painter: &egui::Painter, coord: Coord, color: &Color32) {
-    painter.rect(
-        CELL.translate(egui::Vec2::new(coord.0 as f32, coord.1 as f32)),
-        ROUNDING,
-        *color,
-        STROKE,
-    );
+// Paint one cell, tinting the skin with `color` if the theme has one,
+// and marking it with the glyph of `id` if glyphs are turned on
+fn paint_coord(painter: &egui::Painter, look: &Look<'_>, coord: Coord, color: Color32, id: Option<&BlockID>) {
+    let rect = CELL.translate(egui::Vec2::new(coord.0 as f32, coord.1 as f32));
+    match look.skin {
+        Some(texture) => painter.image(texture, rect, FULL_UV, color),
+        None => painter.rect(rect, look.theme.rounding(), color, look.theme.stroke()),
+    }
+
+    if let Some(glyph) = id.filter(|_| look.glyphs).and_then(|id| look.pieces.get(*id)).and_then(Piece::glyph) {
+        painter.text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            glyph,
+            egui::FontId::monospace(GLYPH_SIZE),
+            contrast_color(color));
+    }
 }
 
-// Choose color based on BlockID
-fn color_from_id(id: &BlockID) -> &Color32 {
-    match id {
-        BlockID::I => &COLOR_I,
-        BlockID::J => &COLOR_J,
-        BlockID::L => &COLOR_L,
-        BlockID::O => &COLOR_O,
-        BlockID::S => &COLOR_S,
-        BlockID::T => &COLOR_T,
-        BlockID::Z => &COLOR_Z,
+// Black on light colors and white on dark colors, as transparent as `color`
+fn contrast_color(color: Color32) -> Color32 {
+    let luminance = 0.299 * color.r() as f32 + 0.587 * color.g() as f32 + 0.114 * color.b() as f32;
+    let alpha = color.a() as f32 / 255.0;
+    if luminance > 140.0 * alpha {
+        Color32::BLACK.linear_multiply(alpha)
+    } else {
+        Color32::WHITE.linear_multiply(alpha)
     }
 }
 
+// Settings of named apps are kept apart from each other and from the unnamed app
+fn storage_key(name: &str, key: &str) -> String {
+    match name {
+        "" => key.to_string(),
+        name => format!("{}/{}", name, key),
+    }
+}
+
+// Seed for a new game
+fn random_seed() -> u64 {
+    chrono::Local::now().timestamp_millis() as u64
+}
+
 // Setup context styles
 fn setup_context(ctx: &egui::Context) {
     use egui::{TextStyle, FontId, FontFamily};
@@ -262,3 +1447,89 @@ fn setup_context(ctx: &egui::Context) {
     ].into();
     ctx.set_style(style);
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs_contrast_with_the_cell() {
+        let theme = Theme::guideline();
+        assert_eq!(contrast_color(theme.blocks[BlockID::O.index()]), Color32::BLACK);
+        assert_eq!(contrast_color(theme.blocks[BlockID::J.index()]), Color32::WHITE);
+        // Hints are see-through, and so are their glyphs
+        assert!(contrast_color(theme.blocks[BlockID::O.index()].linear_multiply(HINT_OPACITY)).a() < 255);
+    }
+    // Settings kept in memory instead of a file
+    #[derive(Default)]
+    struct MemoryStorage(std::collections::HashMap<String, String>);
+
+    impl eframe::Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_string(), value);
+        }
+        fn flush(&mut self) {}
+    }
+
+    // Names the events it is told about
+    struct Recorder(std::rc::Rc<std::cell::RefCell<Vec<&'static str>>>);
+
+    impl GameObserver for Recorder {
+        fn notify(&mut self, event: &GameEvent, _game: &Tetris) {
+            self.0.borrow_mut().push(event.name());
+        }
+    }
+
+    fn new_app(storage: Option<&dyn eframe::Storage>, options: Options, name: &str) -> TetrisApp {
+        let cc = eframe::CreationContext {
+            egui_ctx: egui::Context::default(),
+            integration_info: eframe::IntegrationInfo {
+                system_theme: None,
+                cpu_usage: None,
+                native_pixels_per_point: None,
+                window_info: eframe::WindowInfo {
+                    position: None,
+                    fullscreen: false,
+                    minimized: false,
+                    maximized: false,
+                    size: egui::Vec2::ZERO,
+                    monitor_size: None,
+                },
+            },
+            storage,
+            gl: None,
+        };
+        TetrisApp::new_instance(&cc, options, name)
+    }
+
+    #[test]
+    fn events_reach_listeners_until_they_unsubscribe() {
+        let mut app = new_app(None, Options::default(), "");
+        let events = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let id = app.subscribe(Box::new(Recorder(events.clone())));
+        app.execute(&GameCmd::HardDrop);
+        assert!(events.borrow().contains(&"Locked"), "{:?}", events.borrow());
+
+        events.borrow_mut().clear();
+        assert!(app.unsubscribe(id));
+        assert!(!app.unsubscribe(id));
+        app.execute(&GameCmd::HardDrop);
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn named_apps_keep_their_own_settings() {
+        let mut storage = MemoryStorage::default();
+        let options = Options { theme: Some(Theme::high_contrast()), ..Options::default() };
+        eframe::App::save(&mut new_app(None, options, "left"), &mut storage);
+
+        assert_eq!(new_app(Some(&storage), Options::default(), "left").theme, Theme::high_contrast());
+        assert_eq!(new_app(Some(&storage), Options::default(), "right").theme, Theme::default());
+        assert_eq!(new_app(Some(&storage), Options::default(), "").theme, Theme::default());
+    }
+}
+
@@ -0,0 +1,354 @@
+use serde::{Serialize, Deserialize};
+
+use super::tetris::Tetris;
+use super::events::{GameEvent, GameObserver};
+
+// Notes as (MIDI note number, duration in ms), note 0 is a rest
+type Notes = &'static [(u8, u32)];
+
+const NOTES_MOVE: Notes         = &[(84, 15)];
+const NOTES_ROTATE: Notes       = &[(79, 20), (84, 20)];
+const NOTES_LOCK: Notes         = &[(48, 40)];
+const NOTES_SINGLE: Notes       = &[(72, 70)];
+const NOTES_DOUBLE: Notes       = &[(72, 60), (76, 70)];
+const NOTES_TRIPLE: Notes       = &[(72, 60), (76, 60), (79, 70)];
+const NOTES_TETRIS: Notes       = &[(72, 60), (76, 60), (79, 60), (84, 150)];
+const NOTES_TSPIN: Notes        = &[(67, 50), (70, 50), (74, 50), (79, 120)];
+const NOTES_LEVEL_UP: Notes     = &[(72, 80), (76, 80), (79, 80), (84, 80), (88, 200)];
+const NOTES_GAME_OVER: Notes    = &[(67, 150), (63, 150), (60, 150), (55, 400)];
+
+// Korobeiniki, the traditional Tetris theme (a quarter note is 300 ms)
+const MUSIC: Notes = &[
+    (76, 300), (71, 150), (72, 150), (74, 300), (72, 150), (71, 150),
+    (69, 300), (69, 150), (72, 150), (76, 300), (74, 150), (72, 150),
+    (71, 450), (72, 150), (74, 300), (76, 300),
+    (72, 300), (69, 300), (69, 300), (0, 300),
+    (0, 150), (74, 300), (77, 150), (81, 300), (79, 150), (77, 150),
+    (76, 450), (72, 150), (76, 300), (74, 150), (72, 150),
+    (71, 300), (71, 150), (72, 150), (74, 300), (76, 300),
+    (72, 300), (69, 300), (69, 300), (0, 300),
+];
+
+// Volumes are scaled down, full volume square and sine waves are unpleasantly loud
+const MAX_GAIN: f32 = 0.25;
+
+/// The sound effects of the game
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sound {
+    Move,
+    Rotate,
+    Lock,
+    // One to three lines
+    LineClear(usize),
+    Tetris,
+    TSpin,
+    LevelUp,
+    GameOver,
+}
+
+impl Sound {
+    /// The sound played for a game event, if any
+    pub fn from_event(event: &GameEvent) -> Option<Sound> {
+        match event {
+            GameEvent::Moved { .. } => Some(Sound::Move),
+            GameEvent::Rotated { .. } => Some(Sound::Rotate),
+            GameEvent::Locked { .. } => Some(Sound::Lock),
+            GameEvent::LinesCleared { tspin: true, .. } => Some(Sound::TSpin),
+            GameEvent::LinesCleared { rows, .. } if rows.len() >= 4 => Some(Sound::Tetris),
+            GameEvent::LinesCleared { rows, .. } => Some(Sound::LineClear(rows.len())),
+            GameEvent::LevelUp { .. } => Some(Sound::LevelUp),
+            GameEvent::TopOut { .. } => Some(Sound::GameOver),
+            GameEvent::Spawned { .. } | GameEvent::Held { .. } => None,
+        }
+    }
+
+    fn notes(&self) -> Notes {
+        match self {
+            Sound::Move => NOTES_MOVE,
+            Sound::Rotate => NOTES_ROTATE,
+            Sound::Lock => NOTES_LOCK,
+            Sound::LineClear(1) => NOTES_SINGLE,
+            Sound::LineClear(2) => NOTES_DOUBLE,
+            Sound::LineClear(_) => NOTES_TRIPLE,
+            Sound::Tetris => NOTES_TETRIS,
+            Sound::TSpin => NOTES_TSPIN,
+            Sound::LevelUp => NOTES_LEVEL_UP,
+            Sound::GameOver => NOTES_GAME_OVER,
+        }
+    }
+}
+
+/// Volumes (0 to 1) and whether the music plays
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioSettings {
+    pub volume: f32,
+    pub music_volume: f32,
+    pub music: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self { volume: 0.5, music_volume: 0.3, music: false }
+    }
+}
+
+// Something that can play notes, sound effects may overlap each other and the music
+trait Backend {
+    fn play(&mut self, notes: Notes, gain: f32);
+    // Called every frame, keeps the music looping (or silent if `gain` is 0)
+    fn update_music(&mut self, notes: Notes, gain: f32);
+}
+
+// Used when there is no audio device (or no audio support compiled in)
+struct NullBackend;
+
+impl Backend for NullBackend {
+    fn play(&mut self, _notes: Notes, _gain: f32) {}
+    fn update_music(&mut self, _notes: Notes, _gain: f32) {}
+}
+
+/// Plays the sound effects for game events and the music
+pub struct Audio {
+    backend: Box<dyn Backend>,
+    available: bool,
+    pub settings: AudioSettings,
+}
+
+impl Audio {
+    /// Uses the audio device if there is one, otherwise stays silent
+    pub fn new(settings: AudioSettings) -> Self {
+        match device_backend() {
+            Some(backend) => Self { backend, available: true, settings },
+            None => Self::silent(settings),
+        }
+    }
+
+    /// Never plays anything
+    pub fn silent(settings: AudioSettings) -> Self {
+        Self { backend: Box::new(NullBackend), available: false, settings }
+    }
+
+    /// Whether an audio device is used
+    pub fn is_available(&self) -> bool {
+        self.available
+    }
+
+    pub fn play(&mut self, sound: Sound) {
+        if self.settings.volume > 0.0 {
+            self.backend.play(sound.notes(), MAX_GAIN * self.settings.volume);
+        }
+    }
+
+    /// Plays the sound of the event, if it has one
+    pub fn handle(&mut self, event: &GameEvent) {
+        if let Some(sound) = Sound::from_event(event) {
+            self.play(sound);
+        }
+    }
+
+    /// Keeps the music going, call it every frame
+    pub fn update(&mut self) {
+        let gain = if self.settings.music { MAX_GAIN * self.settings.music_volume } else { 0.0 };
+        self.backend.update_music(MUSIC, gain);
+    }
+}
+
+impl GameObserver for Audio {
+    fn notify(&mut self, event: &GameEvent, _game: &Tetris) {
+        self.handle(event);
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Backends
+
+#[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+fn device_backend() -> Option<Box<dyn Backend>> {
+    native::RodioBackend::new().map(|backend| Box::new(backend) as Box<dyn Backend>)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn device_backend() -> Option<Box<dyn Backend>> {
+    web::WebAudioBackend::new().map(|backend| Box::new(backend) as Box<dyn Backend>)
+}
+
+#[cfg(not(any(feature = "audio", target_arch = "wasm32")))]
+fn device_backend() -> Option<Box<dyn Backend>> {
+    None
+}
+
+#[cfg(any(feature = "audio", target_arch = "wasm32"))]
+fn frequency(note: u8) -> f32 {
+    440.0 * 2f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+#[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+mod native {
+    use std::time::Duration;
+
+    use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+    use rodio::source::{SineWave, Zero};
+
+    use super::{Backend, Notes, frequency};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    // The stream has to be kept alive for the sinks to be heard
+    pub struct RodioBackend {
+        _stream: OutputStream,
+        handle: OutputStreamHandle,
+        music: Option<Sink>,
+    }
+
+    impl RodioBackend {
+        pub fn new() -> Option<Self> {
+            let (stream, handle) = OutputStream::try_default().ok()?;
+            Some(Self { _stream: stream, handle, music: None })
+        }
+
+        fn append(sink: &Sink, notes: Notes) {
+            for &(note, ms) in notes {
+                let duration = Duration::from_millis(ms as u64);
+                match note {
+                    0 => sink.append(Zero::<f32>::new(1, SAMPLE_RATE).take_duration(duration)),
+                    note => sink.append(SineWave::new(frequency(note)).take_duration(duration)),
+                }
+            }
+        }
+    }
+
+    impl Backend for RodioBackend {
+        fn play(&mut self, notes: Notes, gain: f32) {
+            if let Ok(sink) = Sink::try_new(&self.handle) {
+                sink.set_volume(gain);
+                Self::append(&sink, notes);
+                sink.detach();
+            }
+        }
+
+        fn update_music(&mut self, notes: Notes, gain: f32) {
+            if gain <= 0.0 {
+                self.music = None;
+                return
+            }
+            if self.music.is_none() {
+                self.music = Sink::try_new(&self.handle).ok();
+            }
+            if let Some(sink) = &self.music {
+                sink.set_volume(gain);
+                if sink.empty() {
+                    Self::append(sink, notes);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use web_sys::{AudioContext, GainNode, OscillatorType};
+
+    use super::{Backend, Notes, frequency};
+
+    // How long before the end of the music the next loop is scheduled (in seconds)
+    const LOOKAHEAD: f64 = 1.0;
+
+    pub struct WebAudioBackend {
+        context: AudioContext,
+        music_gain: GainNode,
+        music_end: f64,
+    }
+
+    impl WebAudioBackend {
+        pub fn new() -> Option<Self> {
+            let context = AudioContext::new().ok()?;
+            let music_gain = context.create_gain().ok()?;
+            music_gain.connect_with_audio_node(&context.destination()).ok()?;
+            Some(Self { context, music_gain, music_end: 0.0 })
+        }
+
+        // Schedules the notes from `start` on through `output`, returns when the last note ends
+        fn schedule(&self, notes: Notes, output: &GainNode, start: f64) -> f64 {
+            let mut time = start;
+            for &(note, ms) in notes {
+                let end = time + ms as f64 / 1000.0;
+                if note != 0 {
+                    if let Ok(oscillator) = self.context.create_oscillator() {
+                        oscillator.set_type(OscillatorType::Square);
+                        oscillator.frequency().set_value(frequency(note));
+                        if oscillator.connect_with_audio_node(output).is_ok() {
+                            let _ = oscillator.start_with_when(time);
+                            let _ = oscillator.stop_with_when(end);
+                        }
+                    }
+                }
+                time = end;
+            }
+            time
+        }
+    }
+
+    impl Backend for WebAudioBackend {
+        fn play(&mut self, notes: Notes, gain: f32) {
+            // Browsers suspend the context until the page has been interacted with
+            let _ = self.context.resume();
+            if let Ok(output) = self.context.create_gain() {
+                output.gain().set_value(gain);
+                if output.connect_with_audio_node(&self.context.destination()).is_ok() {
+                    self.schedule(notes, &output, self.context.current_time());
+                }
+            }
+        }
+
+        fn update_music(&mut self, notes: Notes, gain: f32) {
+            self.music_gain.gain().set_value(gain);
+            let now = self.context.current_time();
+            if gain > 0.0 && now + LOOKAHEAD > self.music_end {
+                self.music_end = self.schedule(notes, &self.music_gain, self.music_end.max(now));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use super::*;
+
+    // Keeps the gain of every sound effect played
+    struct Recorder(Rc<RefCell<Vec<f32>>>);
+
+    impl Backend for Recorder {
+        fn play(&mut self, _notes: Notes, gain: f32) {
+            self.0.borrow_mut().push(gain);
+        }
+        fn update_music(&mut self, _notes: Notes, _gain: f32) {}
+    }
+
+    fn cleared(lines: usize, tspin: bool) -> GameEvent {
+        GameEvent::LinesCleared { rows: (0..lines).collect(), tspin, perfect_clear: false }
+    }
+
+    #[test]
+    fn clears_sound_by_their_kind() {
+        assert_eq!(Sound::from_event(&cleared(1, false)), Some(Sound::LineClear(1)));
+        assert_eq!(Sound::from_event(&cleared(3, false)), Some(Sound::LineClear(3)));
+        assert_eq!(Sound::from_event(&cleared(4, false)), Some(Sound::Tetris));
+        assert_eq!(Sound::from_event(&cleared(0, true)), Some(Sound::TSpin));
+        assert_eq!(Sound::from_event(&cleared(2, true)), Some(Sound::TSpin));
+        assert_eq!(Sound::from_event(&GameEvent::Held { id: crate::enums::BlockID::T }), None);
+    }
+
+    #[test]
+    fn plays_at_the_set_volume_unless_muted() {
+        let played = Rc::new(RefCell::new(vec![]));
+        let mut audio = Audio { backend: Box::new(Recorder(played.clone())), available: true, settings: AudioSettings::default() };
+        audio.handle(&cleared(1, false));
+        audio.settings.volume = 0.0;
+        audio.handle(&cleared(1, false));
+        assert_eq!(*played.borrow(), [MAX_GAIN * AudioSettings::default().volume]);
+    }
+}
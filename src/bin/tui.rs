@@ -0,0 +1,240 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+// Terminal front end, renders the game with ANSI colors so it can be played over ssh
+#[cfg(not(target_arch = "wasm32"))]
+mod tui {
+    use std::io::{self, Write};
+    use std::time::{Duration, Instant};
+
+    use crossterm::{
+        cursor, execute, queue, terminal,
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+        style::{Color, Print, ResetColor, SetForegroundColor},
+    };
+    use tetris::{
+        Tetris, TickTimer, BlockID, PieceSet, GameCmd, GameStatus, ShiftCmd, RotateCmd,
+        START_PERIOD, MIN_PERIOD, LVL_UP,
+    };
+
+    const GAME_WIDTH: i32 = 10;
+    const GAME_HEIGHT: i32 = 20;
+    const QUEUE_LEN: usize = 5;
+
+    // Every cell is two characters wide to make it roughly square
+    const CELL: &str = "██";
+    const EMPTY: &str = " .";
+    const SIDEPANEL_COL: u16 = 2 * (GAME_WIDTH as u16 + 2) + 3;
+
+    const COLOR_WALL: Color = Color::Grey;
+
+    enum Input {
+        Game(GameCmd),
+        Reset,
+        Quit,
+    }
+
+    // Restores the terminal when dropped, also if the game panics
+    struct RawTerminal;
+
+    impl RawTerminal {
+        fn enter() -> io::Result<Self> {
+            terminal::enable_raw_mode()?;
+            execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+            Ok(Self)
+        }
+    }
+
+    impl Drop for RawTerminal {
+        fn drop(&mut self) {
+            let _ = execute!(io::stdout(), ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+
+    pub fn run() -> io::Result<()> {
+        let _terminal = RawTerminal::enter()?;
+
+        let mut timer       = TickTimer::new(START_PERIOD, MIN_PERIOD, LVL_UP);
+        let mut game        = Tetris::new(GAME_WIDTH, GAME_HEIGHT, random_seed());
+        let mut last_time   = Instant::now();
+
+        loop {
+            draw(&game)?;
+
+            // Wait for input until it is time for the next tick
+            let time = timer.get_time_until_tick().max(0) as u64;
+            if event::poll(Duration::from_millis(time))? {
+                if let Event::Key(key) = event::read()? {
+                    match input_from_key(&key) {
+                        Some(Input::Quit) => return Ok(()),
+                        Some(Input::Reset) => {
+                            timer       = TickTimer::new(START_PERIOD, MIN_PERIOD, LVL_UP);
+                            game        = Tetris::new(GAME_WIDTH, GAME_HEIGHT, random_seed());
+                            queue!(io::stdout(), terminal::Clear(terminal::ClearType::All))?;
+                        },
+                        Some(Input::Game(cmd)) if game.status() == GameStatus::Okay => {
+                            let locked = game.execute(&cmd);
+                            if locked.is_some() {
+                                timer.update_period_from_score(game.score());
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+            }
+
+            // The time spent waiting counts towards the gravity, ticks missed while busy are caught up
+            let elapsed_ms = last_time.elapsed().as_millis() as u64;
+            timer.advance(elapsed_ms as i64);
+            last_time += Duration::from_millis(elapsed_ms);
+            while game.status() == GameStatus::Okay && timer.take_tick() {
+                if game.tick().is_some() {
+                    timer.update_period_from_score(game.score());
+                }
+            }
+        }
+    }
+
+    // Same key bindings as the egui front end
+    fn input_from_key(key: &KeyEvent) -> Option<Input> {
+        if key.kind != KeyEventKind::Press {
+            return None
+        }
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q')       => Some(Input::Quit),
+            KeyCode::Char('r')                      => Some(Input::Reset),
+            KeyCode::Char(' ')                      => Some(Input::Game(GameCmd::HardDrop)),
+            KeyCode::Char('s')                      => Some(Input::Game(GameCmd::SoftDrop)),
+            KeyCode::Char('c')                      => Some(Input::Game(GameCmd::Hold)),
+            KeyCode::Char('h') | KeyCode::Left      => Some(Input::Game(GameCmd::Shift(ShiftCmd::Left))),
+            KeyCode::Char('l') | KeyCode::Right     => Some(Input::Game(GameCmd::Shift(ShiftCmd::Right))),
+            KeyCode::Char('k') | KeyCode::Up        => Some(Input::Game(GameCmd::Rotate(RotateCmd::Right))),
+            KeyCode::Char('j') | KeyCode::Down      => Some(Input::Game(GameCmd::Rotate(RotateCmd::Left))),
+            KeyCode::Char('a')                      => Some(Input::Game(GameCmd::Rotate(RotateCmd::Half))),
+            _ => None,
+        }
+    }
+
+    fn draw(game: &Tetris) -> io::Result<()> {
+        let mut out = io::stdout();
+        let playfield = game.playfield();
+
+        // Playfield with walls, one terminal row per game row
+        for y in 0..=game.height() {
+            queue!(out, cursor::MoveTo(0, y as u16))?;
+            for x in 0..=game.width() + 1 {
+                if x == 0 || x == game.width() + 1 || y == game.height() {
+                    queue!(out, SetForegroundColor(COLOR_WALL), Print(CELL))?;
+                } else if let Some(id) = playfield[y as usize][(x - 1) as usize] {
+                    queue!(out, SetForegroundColor(color_from_id(game.pieces(), &id)), Print(CELL))?;
+                } else {
+                    queue!(out, SetForegroundColor(Color::DarkGrey), Print(EMPTY))?;
+                }
+            }
+        }
+
+        // Falling block on top of the playfield
+        if game.status() == GameStatus::Okay {
+            let block = game.current_block();
+            for coord in block.config().filter(|c| c.1 >= 0) {
+                queue!(
+                    out,
+                    cursor::MoveTo(2 * coord.0 as u16, coord.1 as u16),
+                    SetForegroundColor(color_from_id(game.pieces(), block.id())),
+                    Print(CELL),
+                )?;
+            }
+        }
+
+        // Side panel with score, queue and hold
+        queue!(
+            out,
+            ResetColor,
+            cursor::MoveTo(SIDEPANEL_COL, 0),
+            Print(format!("Score: {} p", game.score())),
+            cursor::MoveTo(SIDEPANEL_COL, 2),
+            Print("Next:"),
+        )?;
+        let mut row = 3;
+        for block in game.peek_queue(QUEUE_LEN) {
+            row = draw_block_preview(&mut out, game.pieces(), Some(block.id()), row)?;
+        }
+        queue!(out, ResetColor, cursor::MoveTo(SIDEPANEL_COL, row), Print("Hold:"))?;
+        draw_block_preview(&mut out, game.pieces(), game.held_block().map(|b| b.id()), row + 1)?;
+
+        if game.status() == GameStatus::GameOver {
+            queue!(
+                out,
+                ResetColor,
+                cursor::MoveTo(SIDEPANEL_COL, game.height() as u16 - 1),
+                Print("Game over! Press r to restart"),
+            )?;
+        }
+
+        out.flush()
+    }
+
+    // Draws the block id as a letter in its color (one row per block) and returns the next free row
+    fn draw_block_preview(out: &mut io::Stdout, pieces: &PieceSet, id: Option<&BlockID>, row: u16) -> io::Result<u16> {
+        queue!(out, cursor::MoveTo(SIDEPANEL_COL, row), terminal::Clear(terminal::ClearType::UntilNewLine))?;
+        if let Some(id) = id {
+            let name = pieces.get(*id).map_or("", |piece| piece.name.as_str());
+            queue!(out, SetForegroundColor(color_from_id(pieces, id)), Print(format!("{}{}", CELL, name)))?;
+        }
+        Ok(row + 1)
+    }
+
+    // The color given in the piece set, garbage cells are grey
+    fn color_from_id(pieces: &PieceSet, id: &BlockID) -> Color {
+        match pieces.get(*id) {
+            Some(piece) => Color::Rgb { r: piece.color.r(), g: piece.color.g(), b: piece.color.b() },
+            None => Color::DarkGrey,
+        }
+    }
+
+    fn random_seed() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crossterm::event::KeyModifiers;
+
+        #[test]
+        fn keys_map_to_commands_on_press() {
+            let press = |code| input_from_key(&KeyEvent::new(code, KeyModifiers::NONE));
+            assert!(matches!(press(KeyCode::Char(' ')), Some(Input::Game(GameCmd::HardDrop))));
+            assert!(matches!(press(KeyCode::Left), Some(Input::Game(GameCmd::Shift(ShiftCmd::Left)))));
+            assert!(matches!(press(KeyCode::Char('a')), Some(Input::Game(GameCmd::Rotate(RotateCmd::Half)))));
+            assert!(matches!(press(KeyCode::Char('q')), Some(Input::Quit)));
+            assert!(press(KeyCode::Char('x')).is_none());
+
+            let mut release = KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE);
+            release.kind = KeyEventKind::Release;
+            assert!(input_from_key(&release).is_none());
+        }
+
+        #[test]
+        fn colors_come_from_the_piece_set() {
+            let pieces = PieceSet::find("Pentomino").unwrap();
+            let colors: Vec<Color> = pieces.ids().map(|id| color_from_id(&pieces, &id)).collect();
+            for (piece, color) in pieces.pieces.iter().zip(&colors) {
+                let (r, g, b) = (piece.color.r(), piece.color.g(), piece.color.b());
+                assert_eq!(*color, Color::Rgb { r, g, b });
+            }
+            assert_eq!(color_from_id(&pieces, &BlockID::G), Color::DarkGrey);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> std::io::Result<()> {
+    tui::run()
+}
+
+// The terminal front end has no meaning in the browser
+#[cfg(target_arch = "wasm32")]
+fn main() {}
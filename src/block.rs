@@ -1,18 +1,22 @@
 use std::ops::Add;
 use std::collections::HashSet;
 
-use super::util::Coord;
+use serde::{Serialize, Deserialize};
+
+use super::util::{Coord, Rng};
+use super::rotation::Orientation;
 use super::enums::{
     BlockID,
     ShiftCmd,
-    RotateCmd,
+    Ruleset,
 };
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Block {
     coords: HashSet<Coord>,
     center: Coord,
     id: BlockID,
+    orientation: Orientation,
 }
 
 impl Add<Coord> for &Block {
@@ -23,48 +27,29 @@ impl Add<Coord> for &Block {
             id: self.id,
             center: self.center+rhs,
             coords: self.coords.iter().map(|&c| c + rhs).collect(),
+            orientation: self.orientation,
         }
     }
 }
 
 impl Block {
-    fn new(id: BlockID) -> Self {
-        match id {
-            BlockID::I => Self {
-                coords: HashSet::from([Coord(0, 0), Coord(1, 0), Coord(2, 0), Coord(3, 0)]),
-                center: Coord(1, 0),
-                id,
-            },
-            BlockID::J => Self {
-                coords: HashSet::from([Coord(0, 0), Coord(0, -1), Coord(1, 0), Coord(2, 0)]),
-                center: Coord(1, 0),
-                id,
-            },
-            BlockID::L => Self {
-                coords: HashSet::from([Coord(0, 0), Coord(2, -1), Coord(1, 0), Coord(2, 0)]),
-                center: Coord(1, 0),
-                id,
-            },
-            BlockID::O => Self {
-                coords: HashSet::from([Coord(0, 0), Coord(0, -1), Coord(1, 0), Coord(1, -1)]),
-                center: Coord(0, 0),
-                id,
-            },
-            BlockID::S => Self {
-                coords: HashSet::from([Coord(0, 0), Coord(1, 0), Coord(1, -1), Coord(2, -1)]),
-                center: Coord(1, 0),
-                id,
-            },
-            BlockID::T => Self {
-                coords: HashSet::from([Coord(0, 0), Coord(1, 0), Coord(1, -1), Coord(2, 0)]),
-                center: Coord(1, 0),
-                id,
-            },
-            BlockID::Z => Self {
-                coords: HashSet::from([Coord(0, -1), Coord(1, -1), Coord(1, 0), Coord(2, 0)]),
-                center: Coord(1, 0),
-                id,
-            },
+    /// The piece `id` made of `cells` and turning around `center`, see `RotationSystem::oriented`
+    pub fn new(id: BlockID, cells: impl IntoIterator<Item=Coord>, center: Coord, orientation: Orientation) -> Self {
+        Self {
+            coords: cells.into_iter().collect(),
+            center,
+            id,
+            orientation,
+        }
+    }
+
+    /// A single cell of the playfield, e.g., garbage or a cell painted in the editor
+    pub fn cell(id: BlockID, coord: Coord) -> Self {
+        Self {
+            coords: HashSet::from([coord]),
+            center: coord,
+            id,
+            orientation: Orientation::Spawn,
         }
     }
 
@@ -92,24 +77,6 @@ impl Block {
         }
     }
 
-    /// todo!()
-    /// TODO: Maybe fix to be mutating and instead Clone in Tetris game
-    pub fn rotated_version(&self, cmd: &RotateCmd) -> Self {
-        if let BlockID::O = self.id {
-            return self.clone()
-        }
-
-        let Coord(cx, cy) = self.center;
-        Self {
-            coords: match cmd {
-                RotateCmd::Right => self.coords.iter().map(|c| Coord(cx + (c.1 - cy), cy - (c.0 - cx))).collect(),
-                RotateCmd::Left  => self.coords.iter().map(|c| Coord(cx - (c.1 - cy), cy + (c.0 - cx))).collect(),
-            },
-            center: self.center,
-            id: self.id,
-        }
-    }
-
     /// todo!()
     pub fn clear_line(&mut self, line: usize) {
         let line = line as i32;
@@ -122,11 +89,26 @@ impl Block {
             }).collect();
     }
 
+    /// Removes a single cell from the block
+    pub fn remove_cell(&mut self, coord: &Coord) {
+        self.coords.remove(coord);
+    }
+
     /// todo!()
     pub fn is_fully_cleared(&self) -> bool {
         self.coords.is_empty()
     }
 
+    /// Rotation center of the block
+    pub fn center(&self) -> Coord {
+        self.center
+    }
+
+    /// Clockwise quarter turns from the spawn orientation
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
     /// todo!()
     pub fn id(&self) -> &BlockID {
         &self.id
@@ -148,37 +130,84 @@ impl Block {
 // BlockGenerator //
 ////////////////////
 
-/// BlockGenerator which generates blocks
+/// BlockGenerator which deals the pieces of a set using the bag randomizer,
+/// i.e., every piece is dealt once in random order before the next bag is shuffled.
+/// Under NES rules every block is rolled on its own instead, see `roll_nes`
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BlockGenerator {
-    idx: usize,
+    bag: Vec<BlockID>,
+    rng: Rng,
+    // Number of pieces in the set
+    pieces: usize,
+    rules: Ruleset,
+    // The block dealt last, the NES randomizer avoids repeating it
+    last: Option<BlockID>,
 }
 
 impl BlockGenerator {
-    /// return a blockgenerator which generates the next block based on rule from closure f
-    pub fn new() -> Self {
-        Self { idx: 0 }
-    }
-
-    pub fn peek_next(&self) -> Option<Block> {
-        match self.idx {
-            0 => Some(Block::new(BlockID::I)),
-            1 => Some(Block::new(BlockID::J)),
-            2 => Some(Block::new(BlockID::L)),
-            3 => Some(Block::new(BlockID::O)),
-            4 => Some(Block::new(BlockID::S)),
-            5 => Some(Block::new(BlockID::T)),
-            6 => Some(Block::new(BlockID::Z)),
-            _ => None,
+    /// return a blockgenerator of a set of `pieces` whose sequence of blocks is fully determined by the seed
+    pub fn new(seed: u64, pieces: usize) -> Self {
+        let mut gen = Self { bag: vec![], rng: Rng::new(seed), pieces, rules: Ruleset::Modern, last: None };
+        gen.refill();
+        gen
+    }
+
+    /// Switches the randomizer, the blocks not dealt yet are dropped
+    pub fn set_rules(&mut self, rules: Ruleset) {
+        self.rules = rules;
+        self.refill();
+    }
+
+    // Shuffle a new bag (Fisher-Yates), blocks are dealt from the back. NES rules roll a single block
+    fn refill(&mut self) {
+        if self.rules == Ruleset::Nes {
+            self.bag = vec![self.roll_nes()];
+            return
+        }
+        self.bag = (0..self.pieces).map(|i| BlockID(i as u8)).collect();
+        for i in (1..self.bag.len()).rev() {
+            let j = self.rng.below(i + 1);
+            self.bag.swap(i, j);
+        }
+    }
+
+    // The NES rolls one more outcome than there are pieces, and rolls once more (without the extra outcome)
+    // if it got the extra one or the last block. The second roll is final, so repeats still happen
+    fn roll_nes(&mut self) -> BlockID {
+        let roll = self.rng.below(self.pieces + 1);
+        if roll < self.pieces && Some(BlockID(roll as u8)) != self.last {
+            return BlockID(roll as u8)
         }
+        BlockID(self.rng.below(self.pieces) as u8)
+    }
+
+    pub fn peek_next(&self) -> Option<BlockID> {
+        self.bag.last().copied()
+    }
+
+    /// Deal `queue` (first element first) before the randomizer continues with new bags
+    pub fn set_queue(&mut self, queue: &[BlockID]) {
+        self.bag = queue.iter().rev().copied().collect();
+        if self.bag.is_empty() {
+            self.refill();
+        }
+    }
+
+    /// The next `n` blocks that will be dealt
+    pub fn peek_queue(&self, n: usize) -> Vec<BlockID> {
+        self.clone().take(n).collect()
     }
 }
 
 impl Iterator for BlockGenerator {
-    type Item = Block;
+    type Item = BlockID;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ele = self.peek_next();
-        self.idx = (self.idx + 1) % 7;
+        let ele = self.bag.pop();
+        self.last = ele;
+        if self.bag.is_empty() {
+            self.refill();
+        }
         ele
     }
 }
@@ -0,0 +1,249 @@
+use std::collections::{HashSet, VecDeque};
+
+use super::block::Block;
+use super::tetris::Tetris;
+use super::rotation::Orientation;
+use super::util::Coord;
+use super::enums::{GameCmd, ShiftCmd, RotateCmd};
+
+// Commands explored when searching for placements (hard drop is tried from every visited state)
+const SEARCH_CMDS: [GameCmd; 6] = [
+    GameCmd::Shift(ShiftCmd::Left),
+    GameCmd::Shift(ShiftCmd::Right),
+    GameCmd::Rotate(RotateCmd::Left),
+    GameCmd::Rotate(RotateCmd::Right),
+    GameCmd::Rotate(RotateCmd::Half),
+    GameCmd::SoftDrop,
+];
+
+/// Weights of the features used to score the board after a placement (higher score is better)
+#[derive(Debug, Copy, Clone)]
+pub struct Heuristic {
+    pub holes: f32,
+    pub aggregate_height: f32,
+    pub bumpiness: f32,
+    pub lines: f32,
+}
+
+impl Default for Heuristic {
+    // Weights tuned by Yiyuan Lee's genetic algorithm for the four feature evaluator
+    fn default() -> Self {
+        Self {
+            holes: -0.35663,
+            aggregate_height: -0.510066,
+            bumpiness: -0.184483,
+            lines: 0.760666,
+        }
+    }
+}
+
+/// A final resting place of a block and the commands that take the current block there
+#[derive(Clone)]
+pub struct Placement {
+    pub block: Block,
+    pub cmds: Vec<GameCmd>,
+    pub score: f32,
+}
+
+impl Placement {
+    /// The cells covered by the placed block, sorted
+    pub fn cells(&self) -> Vec<Coord> {
+        sorted_cells(&self.block)
+    }
+}
+
+/// Bot which searches all reachable placements and picks the best one according to its heuristic
+pub struct Bot {
+    heuristic: Heuristic,
+}
+
+impl Bot {
+    pub fn new(heuristic: Heuristic) -> Self {
+        Self { heuristic }
+    }
+
+    /// Every reachable placement of the current block, and of the block swapped in by hold if possible
+    pub fn placements(&self, game: &Tetris) -> Vec<Placement> {
+        let mut placements = self.search(game, &[]);
+        if game.can_hold() {
+            let mut held = game.clone();
+            held.hold_block_if_feasible();
+            placements.extend(self.search(&held, &[GameCmd::Hold]));
+        }
+        placements
+    }
+
+    /// The highest scoring reachable placement
+    pub fn best_placement(&self, game: &Tetris) -> Option<Placement> {
+        best_of(self.placements(game))
+    }
+
+    /// The placement covering `target` if it is still reachable, otherwise the best placement.
+    /// Re-planning every move keeps the bot on track when gravity moves the block.
+    pub fn plan(&self, game: &Tetris, target: Option<&[Coord]>) -> Option<Placement> {
+        let placements = self.placements(game);
+        match target.and_then(|target| placements.iter().find(|p| p.cells() == target)) {
+            Some(placement) => Some(placement.clone()),
+            None => best_of(placements),
+        }
+    }
+
+    // Breadth first search over block states, so every placement is reached with the fewest commands
+    fn search(&self, game: &Tetris, prefix: &[GameCmd]) -> Vec<Placement> {
+        let start = game.current_block().clone();
+        if !game.is_feasible(&start) {
+            return vec![]
+        }
+
+        let mut visited = HashSet::from([state_key(&start)]);
+        let mut landed = HashSet::new();
+        let mut queue = VecDeque::from([(start, prefix.to_vec())]);
+        let mut placements = vec![];
+
+        while let Some((block, cmds)) = queue.pop_front() {
+            let landing = drop_to_floor(game, &block);
+            if landed.insert(sorted_cells(&landing)) {
+                let mut path = cmds.clone();
+                path.push(GameCmd::HardDrop);
+                let score = self.evaluate(game, &landing);
+                placements.push(Placement { block: landing, cmds: path, score });
+            }
+
+            for cmd in SEARCH_CMDS {
+                let next = match cmd {
+                    GameCmd::Shift(shift) => Some(block.shifted_version(&shift)),
+                    GameCmd::Rotate(rotate) => game.rotated(&block, &rotate).map(|(block, _)| block),
+                    _ => Some(block.drop_one()),
+                };
+                let Some(next) = next.filter(|next| game.is_feasible(next)) else { continue };
+                if visited.insert(state_key(&next)) {
+                    let mut path = cmds.clone();
+                    path.push(cmd);
+                    queue.push_back((next, path));
+                }
+            }
+        }
+        placements
+    }
+
+    // Scores the board that results from locking `landing` into the game state
+    fn evaluate(&self, game: &Tetris, landing: &Block) -> f32 {
+        let (width, height) = (game.width(), game.height());
+        let cells: HashSet<Coord> = game.state_config()
+            .flat_map(|block| block.config())
+            .chain(landing.config())
+            .copied()
+            .collect();
+
+        // Remove full lines and let everything above fall down
+        let full_lines: Vec<i32> = landing.config()
+            .map(|c| c.1)
+            .collect::<HashSet<i32>>()
+            .into_iter()
+            .filter(|&y| (1..=width).all(|x| cells.contains(&Coord(x, y))))
+            .collect();
+        let cells: HashSet<Coord> = cells.into_iter()
+            .filter(|c| !full_lines.contains(&c.1))
+            .map(|c| Coord(c.0, c.1 + full_lines.iter().filter(|&&y| y > c.1).count() as i32))
+            .collect();
+
+        let heights: Vec<i32> = (1..=width)
+            .map(|x| cells.iter().filter(|c| c.0 == x).map(|c| height - c.1).max().unwrap_or(0))
+            .collect();
+        let holes = (1..=width)
+            .zip(&heights)
+            .map(|(x, h)| (height - h..height).filter(|&y| !cells.contains(&Coord(x, y))).count())
+            .sum::<usize>();
+        let aggregate_height: i32 = heights.iter().sum();
+        let bumpiness: i32 = heights.windows(2).map(|w| (w[0] - w[1]).abs()).sum();
+
+        self.heuristic.holes * holes as f32
+            + self.heuristic.aggregate_height * aggregate_height as f32
+            + self.heuristic.bumpiness * bumpiness as f32
+            + self.heuristic.lines * full_lines.len() as f32
+    }
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self::new(Heuristic::default())
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+fn best_of(placements: Vec<Placement>) -> Option<Placement> {
+    placements.into_iter().max_by(|a, b| a.score.total_cmp(&b.score))
+}
+
+pub(crate) fn drop_to_floor(game: &Tetris, block: &Block) -> Block {
+    let mut block = block.clone();
+    loop {
+        let dropped = block.drop_one();
+        if !game.is_feasible(&dropped) {
+            return block
+        }
+        block = dropped;
+    }
+}
+
+pub(crate) fn sorted_cells(block: &Block) -> Vec<Coord> {
+    let mut cells: Vec<Coord> = block.config().copied().collect();
+    cells.sort();
+    cells
+}
+
+// The center and orientation are part of the key since the same cells can turn and kick differently
+pub(crate) fn state_key(block: &Block) -> (Vec<Coord>, Coord, Orientation) {
+    (sorted_cells(block), block.center(), block.orientation())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::BlockID;
+    use crate::events::GameEvent;
+
+    // The cells locked by the commands of the placement
+    fn locked_cells(game: &Tetris, placement: &Placement) -> Vec<Coord> {
+        let mut game = game.clone();
+        placement.cmds.iter().for_each(|cmd| { game.execute(cmd); });
+        let mut cells = game.drain_events().into_iter()
+            .find_map(|event| match event {
+                GameEvent::Locked { cells, .. } => Some(cells),
+                _ => None,
+            })
+            .unwrap_or_default();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn placements_are_reached_by_their_commands() {
+        let game = Tetris::new(10, 20, 3);
+        let placements = Bot::default().placements(&game);
+        assert!(placements.iter().any(|placement| placement.cmds[0] == GameCmd::Hold));
+        for placement in &placements {
+            assert_eq!(locked_cells(&game, placement), placement.cells());
+        }
+    }
+
+    #[test]
+    fn best_placement_fills_the_well() {
+        let mut game = Tetris::new(10, 20, 1);
+        let mut field = vec![vec![None; 10]; 20];
+        for row in &mut field[16..] {
+            row[..9].fill(Some(BlockID::G));
+        }
+        game.set_playfield(&field);
+        game.spawn_current_block(BlockID::I);
+
+        let placement = Bot::default().best_placement(&game).unwrap();
+        let mut expected: Vec<Coord> = (16..20).map(|y| Coord(10, y)).collect();
+        expected.sort();
+        assert_eq!(placement.cells(), expected);
+        placement.cmds.iter().for_each(|cmd| { game.execute(cmd); });
+        assert_eq!(game.lines(), 4);
+    }
+}
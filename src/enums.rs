@@ -1,14 +1,132 @@
-#[derive(Debug, Copy, Clone)]
-pub enum BlockID {
-    I, J, L, O, S, T, Z,
+use serde::{Serialize, Deserialize};
+
+use super::options::MAX_LEVEL;
+
+/// A piece of the piece set the game is played with, by its position in the set
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockID(pub u8);
+
+impl BlockID {
+    // The tetrominoes, as positioned in the standard piece set
+    pub const I: BlockID = BlockID(0);
+    pub const J: BlockID = BlockID(1);
+    pub const L: BlockID = BlockID(2);
+    pub const O: BlockID = BlockID(3);
+    pub const S: BlockID = BlockID(4);
+    pub const T: BlockID = BlockID(5);
+    pub const Z: BlockID = BlockID(6);
+    // Garbage, single cells which are part of the playfield but never dealt as a block
+    pub const G: BlockID = BlockID(u8::MAX);
+
+    // The tetrominoes
+    pub const ALL: [BlockID; 7] = [BlockID::I, BlockID::J, BlockID::L, BlockID::O, BlockID::S, BlockID::T, BlockID::Z];
+
+    /// Position of the piece in its set
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+
+    /// The tetromino named by the letter (case insensitive)
+    pub fn from_char(c: char) -> Option<BlockID> {
+        "IJLOSTZ".find(c.to_ascii_uppercase()).map(|i| BlockID(i as u8))
+    }
+
+    /// Letter of the tetromino, `None` for garbage
+    pub fn to_char(&self) -> Option<char> {
+        "IJLOSTZ".chars().nth(self.index())
+    }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ShiftCmd {
     Left, Right,
 }
 
+// Left and right are quarter turns (counter-clockwise and clockwise), half is a 180° turn
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RotateCmd {
-    Left, Right,
+    Left, Right, Half,
+}
+
+/// Every command a player (human or bot) can issue to the game
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GameCmd {
+    Shift(ShiftCmd),
+    Rotate(RotateCmd),
+    SoftDrop,
+    HardDrop,
+    Hold,
+}
+
+/// Rule set of a game
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameMode {
+    // Play until topping out, gravity increases with the score
+    Marathon,
+    // Marathon where placements can be taken back, so it does not count as a ranked game
+    Practice,
+    // Free editing of the board, queue and falling block, with optional gravity
+    Sandbox,
+    // Classic NES Tetris, see `Ruleset::Nes`
+    Nes,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 4] = [GameMode::Marathon, GameMode::Practice, GameMode::Sandbox, GameMode::Nes];
+
+    /// The mode named (case insensitive) like the variant
+    pub fn from_name(name: &str) -> Option<GameMode> {
+        Self::ALL.into_iter().find(|mode| format!("{:?}", mode).eq_ignore_ascii_case(name))
+    }
+
+    /// Ranked games have to be played straight through, so undo is disabled
+    pub fn is_ranked(&self) -> bool {
+        matches!(self, GameMode::Marathon | GameMode::Nes)
+    }
+
+    /// Scoring, randomizer and levels of the mode
+    pub fn ruleset(&self) -> Ruleset {
+        match self {
+            GameMode::Nes => Ruleset::Nes,
+            _ => Ruleset::Modern,
+        }
+    }
+}
+
+/// How a game scores, deals its blocks and speeds up
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ruleset {
+    // Bag randomizer, hold and initial rotation, the level goes up every `LVL_UP` points and halves the gravity
+    #[default]
+    Modern,
+    // NES Tetris: 40/100/300/1200 points × (level + 1), a random block rerolled once if it repeats the last one,
+    // no hold, the NES speed table, and a level every 10 lines once the start level is left behind
+    Nes,
+}
+
+impl Ruleset {
+    /// Levels a game can start at
+    pub fn start_levels(&self) -> std::ops::RangeInclusive<usize> {
+        match self {
+            Ruleset::Modern => 1..=MAX_LEVEL,
+            Ruleset::Nes => 0..=19,
+        }
+    }
+
+    /// Whether blocks can be held
+    pub fn has_hold(&self) -> bool {
+        *self == Ruleset::Modern
+    }
+
+    /// Whether blocks can turn 180° at once
+    pub fn has_half_turn(&self) -> bool {
+        *self == Ruleset::Modern
+    }
+
+    /// Whether rotations and hold given in the delays apply to the next block (IRS/IHS)
+    pub fn has_initial_input(&self) -> bool {
+        *self == Ruleset::Modern
+    }
 }
 
 #[derive(PartialEq)]
@@ -16,3 +134,22 @@ pub enum GameStatus {
     Okay,
     GameOver,
 }
+
+// How the game was lost
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TopOut {
+    // The next block overlaps the playfield (or a wall) where it spawns
+    BlockOut,
+    // A block locked entirely above the visible board
+    LockOut,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Phase {
+    // The current block can be moved and falls with gravity
+    Falling,
+    // Full rows stay on the board until the delay has passed
+    LineClear { rows: Vec<usize>, remaining_ms: i64 },
+    // Entry delay (ARE) before the next block appears
+    Entry { remaining_ms: i64 },
+}
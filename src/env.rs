@@ -0,0 +1,287 @@
+use std::fmt;
+
+use super::tetris::Tetris;
+use super::bot::{Bot, Placement};
+use super::enums::{BlockID, ShiftCmd, RotateCmd, GameCmd, GameStatus};
+use super::util::Coord;
+
+/// Commands of the per-keypress action space, indexed by the action number (action 0 does nothing)
+pub const KEY_ACTIONS: [Option<GameCmd>; 9] = [
+    None,
+    Some(GameCmd::Shift(ShiftCmd::Left)),
+    Some(GameCmd::Shift(ShiftCmd::Right)),
+    Some(GameCmd::Rotate(RotateCmd::Left)),
+    Some(GameCmd::Rotate(RotateCmd::Right)),
+    Some(GameCmd::SoftDrop),
+    Some(GameCmd::HardDrop),
+    Some(GameCmd::Hold),
+    Some(GameCmd::Rotate(RotateCmd::Half)),
+];
+
+/// What one step (and thereby one action) of the environment corresponds to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ActionSpace {
+    /// One command per step, the action indexes `KEY_ACTIONS`
+    Keypress,
+    /// One locked block per step, the action indexes `Env::placements()`
+    Placement,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EnvConfig {
+    pub width: i32,
+    pub height: i32,
+    pub action_space: ActionSpace,
+    // Gravity pulls the block down one row every `gravity_steps` steps (keypress action space only)
+    pub gravity_steps: usize,
+    // How many upcoming blocks are part of the observation
+    pub queue_len: usize,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            height: 20,
+            action_space: ActionSpace::Placement,
+            gravity_steps: 10,
+            queue_len: 5,
+        }
+    }
+}
+
+/// What the agent gets to see of the game
+#[derive(Debug, Clone)]
+pub struct Observation {
+    /// Locked cells row by row from the top, 0 is empty and otherwise `BlockID::index() + 1` (garbage is 255)
+    pub board: Vec<Vec<u8>>,
+    /// Cells (x, y) of the falling block in the same coordinates as `board`
+    pub current: Vec<(i32, i32)>,
+    pub current_id: BlockID,
+    pub queue: Vec<BlockID>,
+    pub hold: Option<BlockID>,
+}
+
+/// Diagnostics of a step which are not part of the observation
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Info {
+    pub lines_cleared: usize,
+    pub score: usize,
+    pub total_lines: usize,
+    pub pieces: usize,
+    pub steps: usize,
+    // Number of valid actions in the next step
+    pub num_actions: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EnvError {
+    InvalidAction { action: usize, num_actions: usize },
+    GameOver,
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvError::InvalidAction { action, num_actions } =>
+                write!(f, "action {} is out of range, there are {} valid actions", action, num_actions),
+            EnvError::GameOver => write!(f, "the game is over, call reset before stepping again"),
+        }
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+/// Gym style environment. Time only advances through `step`, so it runs as fast as the agent does.
+pub struct Env {
+    config: EnvConfig,
+    game: Tetris,
+    bot: Bot,
+    placements: Vec<Placement>,
+    info: Info,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Self {
+        let mut env = Self {
+            config,
+            game: Tetris::new(config.width, config.height, 0),
+            bot: Bot::default(),
+            placements: vec![],
+            info: Info::default(),
+        };
+        env.reset(0);
+        env
+    }
+
+    /// Starts a new game whose block sequence is determined by `seed`
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.game       = Tetris::new(self.config.width, self.config.height, seed);
+        self.info       = Info::default();
+        self.update_actions();
+        self.observation()
+    }
+
+    /// Performs `action` and returns the resulting observation, the reward (score gained),
+    /// whether the game is over and diagnostics
+    pub fn step(&mut self, action: usize) -> Result<(Observation, f32, bool, Info), EnvError> {
+        if let GameStatus::GameOver = self.game.status() {
+            return Err(EnvError::GameOver)
+        }
+        if action >= self.info.num_actions {
+            return Err(EnvError::InvalidAction { action, num_actions: self.info.num_actions })
+        }
+
+        let score_before = self.game.score();
+        self.info.steps += 1;
+        self.info.lines_cleared = 0;
+
+        match self.config.action_space {
+            ActionSpace::Keypress => {
+                let mut locked = match KEY_ACTIONS[action] {
+                    Some(cmd) => self.game.execute(&cmd),
+                    None => None,
+                };
+                if locked.is_none() && self.info.steps.is_multiple_of(self.config.gravity_steps.max(1)) {
+                    locked = self.game.tick();
+                }
+                if let Some(num_lines_cleared) = locked {
+                    self.lock(num_lines_cleared);
+                }
+            },
+            ActionSpace::Placement => {
+                let cmds = self.placements[action].cmds.clone();
+                for cmd in &cmds {
+                    if let Some(num_lines_cleared) = self.game.execute(cmd) {
+                        self.lock(num_lines_cleared);
+                    }
+                }
+            },
+        }
+
+        self.update_actions();
+        let reward = (self.game.score() - score_before) as f32;
+        let done = self.game.status() == GameStatus::GameOver;
+        Ok((self.observation(), reward, done, self.info))
+    }
+
+    /// The reachable placements of the current block, i.e., the placement action space of the next step
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    /// Cells (x, y) of every placement in the same coordinates as `Observation::board`, indexed like `placements()`
+    pub fn placement_cells(&self) -> Vec<Vec<(i32, i32)>> {
+        self.placements.iter().map(|placement| placement.cells().iter().map(board_cell).collect()).collect()
+    }
+
+    /// Number of valid actions in the next step
+    pub fn num_actions(&self) -> usize {
+        self.info.num_actions
+    }
+
+    /// The underlying game
+    pub fn game(&self) -> &Tetris {
+        &self.game
+    }
+
+    /// The current state of the game as seen by the agent
+    pub fn observation(&self) -> Observation {
+        let board = self.game.playfield().iter()
+            .map(|row| row.iter().map(|cell| cell.map_or(0, |id| id.0.saturating_add(1))).collect())
+            .collect();
+        let current = self.game.current_block().config().map(board_cell).collect();
+
+        Observation {
+            board,
+            current,
+            current_id: *self.game.current_block().id(),
+            queue: self.game.peek_queue(self.config.queue_len).iter().map(|b| *b.id()).collect(),
+            hold: self.game.held_block().map(|b| *b.id()),
+        }
+    }
+
+    // Book keeping when a block has been locked
+    fn lock(&mut self, num_lines_cleared: usize) {
+        self.info.pieces        += 1;
+        self.info.lines_cleared += num_lines_cleared;
+        self.info.total_lines   += num_lines_cleared;
+        self.info.score          = self.game.score();
+    }
+
+    fn update_actions(&mut self) {
+        self.placements = match self.config.action_space {
+            ActionSpace::Placement if self.game.status() == GameStatus::Okay => self.bot.placements(&self.game),
+            _ => vec![],
+        };
+        self.info.num_actions = match self.config.action_space {
+            ActionSpace::Keypress => KEY_ACTIONS.len(),
+            ActionSpace::Placement => self.placements.len(),
+        };
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+// Board coordinates start inside the left wall
+fn board_cell(coord: &Coord) -> (i32, i32) {
+    (coord.0 - 1, coord.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placements_are_in_board_coordinates() {
+        let mut env = Env::new(EnvConfig::default());
+        let cells = env.placement_cells();
+        assert_eq!(cells.len(), env.num_actions());
+        let leftmost = cells.iter().flatten().map(|&(x, _)| x).min();
+        assert_eq!(leftmost, Some(0));
+
+        let (observation, _, _, _) = env.step(0).unwrap();
+        for &(x, y) in &cells[0] {
+            assert_ne!(observation.board[y as usize][x as usize], 0);
+        }
+    }
+
+    #[test]
+    fn placement_steps_lock_a_block_each_until_the_game_is_over() {
+        let mut env = Env::new(EnvConfig::default());
+        assert_eq!(env.reset(7).board, Env::new(EnvConfig::default()).reset(7).board);
+        assert!(matches!(env.step(env.num_actions()), Err(EnvError::InvalidAction { .. })));
+
+        let mut steps = 0;
+        loop {
+            let score = env.game().score();
+            let (_, reward, done, info) = env.step(0).unwrap();
+            steps += 1;
+            assert_eq!(info.pieces, steps);
+            assert_eq!(reward, (env.game().score() - score) as f32);
+            if done {
+                break
+            }
+        }
+        assert!(matches!(env.step(0), Err(EnvError::GameOver)));
+    }
+
+    #[test]
+    fn keypress_steps_fall_with_gravity() {
+        let config = EnvConfig { action_space: ActionSpace::Keypress, gravity_steps: 2, ..EnvConfig::default() };
+        let mut env = Env::new(config);
+        assert_eq!(env.num_actions(), KEY_ACTIONS.len());
+        let top = |observation: &Observation| observation.current.iter().map(|c| c.1).min();
+
+        let start = top(&env.observation());
+        let (observation, _, _, _) = env.step(0).unwrap();
+        assert_eq!(top(&observation), start);
+        let (observation, _, _, _) = env.step(0).unwrap();
+        assert_eq!(top(&observation), start.map(|y| y + 1));
+
+        let hard_drop = KEY_ACTIONS.iter().position(|&cmd| cmd == Some(GameCmd::HardDrop)).unwrap();
+        let (_, _, _, info) = env.step(hard_drop).unwrap();
+        assert_eq!(info.pieces, 1);
+    }
+}
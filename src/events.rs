@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+use super::tetris::Tetris;
+use super::util::Coord;
+use super::enums::{BlockID, ShiftCmd, RotateCmd, TopOut};
+
+// Events that nobody collects are dropped, oldest first, so headless games don't pile them up
+const MAX_EVENTS: usize = 256;
+
+/// Something that happened in the game, collected with `Tetris::drain_events`.
+/// Serializes to an object with the name of the event as `type`, e.g., `{"type":"LevelUp","level":2}`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum GameEvent {
+    // A new block entered the board: the first one, from the queue or the hold, or dealt again for new rules or rotation
+    Spawned { id: BlockID },
+    // The current block was shifted one column
+    Moved { shift: ShiftCmd },
+    // The current block was rotated, `kick` is the offset it was kicked by (`Coord(0, 0)` if it rotated in place)
+    Rotated { rotate: RotateCmd, kick: Coord },
+    // The current block was put into the hold, `Spawned` follows for the block that replaces it
+    Held { id: BlockID },
+    // The current block locked into the playfield at `cells`
+    Locked { id: BlockID, cells: Vec<Coord> },
+    // Full rows were cleared by the last lock, the rows are counted before anything collapsed.
+    // A T-spin is a clear even without rows
+    LinesCleared { rows: Vec<usize>, tspin: bool, perfect_clear: bool },
+    // The score reached the next level
+    LevelUp { level: usize },
+    // The game is lost
+    TopOut { kind: TopOut },
+}
+
+impl GameEvent {
+    /// Name of the event, as in the `type` of the serialized event
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameEvent::Spawned { .. } => "Spawned",
+            GameEvent::Moved { .. } => "Moved",
+            GameEvent::Rotated { .. } => "Rotated",
+            GameEvent::Held { .. } => "Held",
+            GameEvent::Locked { .. } => "Locked",
+            GameEvent::LinesCleared { .. } => "LinesCleared",
+            GameEvent::LevelUp { .. } => "LevelUp",
+            GameEvent::TopOut { .. } => "TopOut",
+        }
+    }
+}
+
+/// Anything that follows a game, e.g., sound, statistics or animations
+pub trait GameObserver {
+    /// Called for every event in order, `game` is the state right after the events happened
+    fn notify(&mut self, event: &GameEvent, game: &Tetris);
+}
+
+/// Hands each of the events to every observer
+pub fn dispatch(events: &[GameEvent], game: &Tetris, observers: &mut [&mut dyn GameObserver]) {
+    for event in events {
+        for observer in observers.iter_mut() {
+            observer.notify(event, game);
+        }
+    }
+}
+
+/// Bounded queue of the events that have not been collected yet
+#[derive(Debug, Clone, Default)]
+pub struct EventQueue {
+    events: VecDeque<GameEvent>,
+}
+
+impl EventQueue {
+    pub fn push(&mut self, event: GameEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// All events in the order they happened, the queue is empty afterwards
+    pub fn drain(&mut self) -> Vec<GameEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::GameCmd;
+
+    #[test]
+    fn a_clearing_drop_locks_clears_and_spawns_in_order() {
+        // A flat I fills the bottom row of a board as wide as it
+        let mut game = Tetris::new(4, 20, 1);
+        game.spawn_current_block(BlockID::I);
+        game.drain_events();
+        game.execute(&GameCmd::HardDrop);
+        let names: Vec<&str> = game.drain_events().iter().map(GameEvent::name).collect();
+        assert_eq!(names, ["Locked", "LinesCleared", "Spawned"]);
+    }
+
+    #[test]
+    fn queue_drops_the_oldest_events() {
+        let mut queue = EventQueue::default();
+        for level in 0..MAX_EVENTS + 2 {
+            queue.push(GameEvent::LevelUp { level });
+        }
+        let events = queue.drain();
+        assert_eq!(events.len(), MAX_EVENTS);
+        assert_eq!(events[0], GameEvent::LevelUp { level: 2 });
+        assert!(queue.drain().is_empty());
+    }
+
+    #[test]
+    fn serializes_with_the_name_as_type() {
+        let event = GameEvent::LevelUp { level: 2 };
+        assert_eq!(serde_json::to_string(&event).unwrap(), r#"{"type":"LevelUp","level":2}"#);
+        assert_eq!(serde_json::to_value(&event).unwrap()["type"], event.name());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::tetris::Tetris;
+use super::block::Block;
+use super::events::{GameEvent, GameObserver};
+use super::util::Coord;
+use super::enums::{GameCmd, ShiftCmd, RotateCmd};
+use super::bot::{drop_to_floor, sorted_cells, state_key};
+
+/// Inputs the player used for a block compared to the fewest that reach the same placement
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FinesseResult {
+    pub inputs: usize,
+    pub optimal: usize,
+}
+
+impl FinesseResult {
+    pub fn is_fault(&self) -> bool {
+        self.inputs > self.optimal
+    }
+}
+
+/// Summary over every judged block, kept for the whole session
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FinesseStats {
+    pub pieces: usize,
+    pub faults: usize,
+    pub inputs: usize,
+    pub optimal_inputs: usize,
+    // Inputs beyond the fewest, added up block by block so blocks placed with fewer inputs don't hide faults
+    pub extra_inputs: usize,
+}
+
+impl FinesseStats {
+    /// Share of blocks placed without a fault, 1 if nothing was placed yet
+    pub fn accuracy(&self) -> f32 {
+        match self.pieces {
+            0 => 1.0,
+            n => (n - self.faults) as f32 / n as f32,
+        }
+    }
+}
+
+/// Counts the shift/rotate inputs of every block and judges them when the block locks
+#[derive(Default)]
+pub struct Finesse {
+    optimal: Option<HashMap<Vec<Coord>, usize>>,
+    inputs: usize,
+    last: Option<FinesseResult>,
+    stats: FinesseStats,
+}
+
+impl Finesse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new block entered the board (spawn, hold, undo, edits ...), its inputs are counted from here
+    pub fn spawn(&mut self, game: &Tetris) {
+        self.optimal = Some(min_inputs(game));
+        self.inputs = 0;
+    }
+
+    /// Count a command issued to the current block
+    pub fn input(&mut self, cmd: &GameCmd) {
+        if matches!(cmd, GameCmd::Shift(_) | GameCmd::Rotate(_)) {
+            self.inputs += 1;
+        }
+    }
+
+    /// Judge the block that locked at `cells`, nothing is judged if the block was not seen spawning
+    pub fn lock(&mut self, cells: &[Coord]) -> Option<FinesseResult> {
+        let mut cells = cells.to_vec();
+        cells.sort();
+        let optimal = *self.optimal.take()?.get(&cells)?;
+        let result = FinesseResult { inputs: self.inputs, optimal };
+
+        self.stats.pieces += 1;
+        self.stats.faults += result.is_fault() as usize;
+        self.stats.inputs += result.inputs;
+        self.stats.optimal_inputs += result.optimal;
+        self.stats.extra_inputs += result.inputs.saturating_sub(result.optimal);
+        self.last = Some(result);
+        Some(result)
+    }
+
+    /// Inputs used so far for the current block
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Result of the last judged block
+    pub fn last(&self) -> Option<FinesseResult> {
+        self.last
+    }
+
+    pub fn stats(&self) -> &FinesseStats {
+        &self.stats
+    }
+}
+
+impl GameObserver for Finesse {
+    fn notify(&mut self, event: &GameEvent, game: &Tetris) {
+        match event {
+            GameEvent::Spawned { .. } => self.spawn(game),
+            GameEvent::Locked { cells, .. } => { self.lock(cells); },
+            _ => (),
+        }
+    }
+}
+
+/// Fewest shift/rotate inputs from the current position to each final placement (by sorted cells).
+/// 0-1 breadth first search over block states, soft drops cost nothing so tucks and spins are judged fairly.
+/// Holding a shift until the block stops at a wall (DAS) is a single input, as its repeats are not counted.
+pub fn min_inputs(game: &Tetris) -> HashMap<Vec<Coord>, usize> {
+    let mut optimal = HashMap::new();
+    let start = game.current_block().clone();
+    if !game.is_feasible(&start) {
+        return optimal
+    }
+
+    let mut distance = HashMap::from([(state_key(&start), 0)]);
+    let mut queue = VecDeque::from([(start, 0)]);
+
+    while let Some((block, inputs)) = queue.pop_front() {
+        if distance[&state_key(&block)] < inputs {
+            continue
+        }
+
+        // States come out in order of inputs, so the first visit of a placement is the cheapest
+        optimal.entry(sorted_cells(&drop_to_floor(game, &block))).or_insert(inputs);
+
+        let next_states = [
+            (Some(block.drop_one()), inputs),
+            (Some(block.shifted_version(&ShiftCmd::Left)), inputs + 1),
+            (Some(block.shifted_version(&ShiftCmd::Right)), inputs + 1),
+            (shifted_to_wall(game, &block, &ShiftCmd::Left), inputs + 1),
+            (shifted_to_wall(game, &block, &ShiftCmd::Right), inputs + 1),
+            (game.rotated(&block, &RotateCmd::Left).map(|(block, _)| block), inputs + 1),
+            (game.rotated(&block, &RotateCmd::Right).map(|(block, _)| block), inputs + 1),
+            (game.rotated(&block, &RotateCmd::Half).map(|(block, _)| block), inputs + 1),
+        ];
+        for (next, cost) in next_states {
+            let Some(next) = next.filter(|next| game.is_feasible(next)) else { continue };
+            let key = state_key(&next);
+            if distance.get(&key).is_none_or(|&d| cost < d) {
+                distance.insert(key, cost);
+                if cost == inputs {
+                    queue.push_front((next, cost));
+                } else {
+                    queue.push_back((next, cost));
+                }
+            }
+        }
+    }
+    optimal
+}
+
+// Where a held shift takes the block, `None` if it can't move at all
+fn shifted_to_wall(game: &Tetris, block: &Block, shift: &ShiftCmd) -> Option<Block> {
+    let mut shifted = block.shifted_version(shift);
+    if !game.is_feasible(&shifted) {
+        return None
+    }
+    loop {
+        let next = shifted.shifted_version(shift);
+        if !game.is_feasible(&next) {
+            return Some(shifted)
+        }
+        shifted = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn das_to_the_wall_is_one_input() {
+        let game = Tetris::new(10, 20, 1);
+        let optimal = min_inputs(&game);
+        for shift in [ShiftCmd::Left, ShiftCmd::Right] {
+            let block = shifted_to_wall(&game, game.current_block(), &shift).unwrap();
+            assert_eq!(optimal[&sorted_cells(&drop_to_floor(&game, &block))], 1);
+        }
+    }
+
+    #[test]
+    fn extra_inputs_add_up_per_block() {
+        let mut finesse = Finesse::new();
+        for (inputs, optimal) in [(3, 1), (0, 2)] {
+            finesse.optimal = Some(HashMap::from([(vec![], optimal)]));
+            finesse.inputs = inputs;
+            finesse.lock(&[]);
+        }
+        assert_eq!(finesse.stats().extra_inputs, 2);
+        assert_eq!(finesse.stats().faults, 1);
+    }
+}
@@ -0,0 +1,717 @@
+use std::fmt;
+
+use super::block::Block;
+use super::tetris::Tetris;
+use super::pieces::PieceSet;
+use super::rotation::Orientation;
+use super::util::Coord;
+use super::enums::BlockID;
+
+// Fumen (https://fumen.zui.jp) encodes boards as base64 like digits, least significant digit first
+const ENCODING_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_TABLE: &[u8] = b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const COMMENT_CHAR_VALUES: u32 = COMMENT_TABLE.len() as u32 + 1;
+
+pub const FIELD_WIDTH: usize = 10;
+const FIELD_TOP_V115: usize = 23;
+const FIELD_TOP_V110: usize = 21;
+
+// Prefix of comments which describe the hold, current block and queue, e.g., "#Q=[Z](T)IOSLJ"
+const QUIZ_PREFIX: &str = "#Q=";
+const QUEUE_LEN: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FumenError {
+    UnsupportedVersion,
+    InvalidCharacter(char),
+    UnexpectedEnd,
+    InvalidValue(u32),
+    UnsupportedWidth(i32),
+    UnsupportedHeight(i32),
+    UnsupportedPieces(String),
+    InvalidPiece,
+}
+
+impl fmt::Display for FumenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FumenError::UnsupportedVersion => write!(f, "only v115 and v110 fumen strings are supported"),
+            FumenError::InvalidCharacter(c) => write!(f, "invalid character '{}' in fumen data", c),
+            FumenError::UnexpectedEnd => write!(f, "fumen data ended unexpectedly"),
+            FumenError::InvalidValue(v) => write!(f, "invalid value {} in fumen data", v),
+            FumenError::UnsupportedWidth(w) => write!(f, "fumen boards are {} wide, not {}", FIELD_WIDTH, w),
+            FumenError::UnsupportedHeight(h) => write!(f, "fumen boards are at most {} high, not {}", FIELD_TOP_V115, h),
+            FumenError::UnsupportedPieces(name) => write!(f, "fumen only knows tetrominoes, not the {} pieces", name),
+            FumenError::InvalidPiece => write!(f, "the piece does not fit on the board"),
+        }
+    }
+}
+
+impl std::error::Error for FumenError {}
+
+/// Orientation of a piece in fumen terms
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FumenRotation {
+    Spawn, Right, Reverse, Left,
+}
+
+/// A piece on a fumen page. `x` and `y` is the position of the rotation center, `y = 0` is the bottom row.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FumenPiece {
+    pub id: BlockID,
+    pub rotation: FumenRotation,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// One page (diagram) of a fumen
+#[derive(Debug, Clone, PartialEq)]
+pub struct FumenPage {
+    /// Rows from the bottom (`field[0]`) and up, `FIELD_WIDTH` cells each
+    pub field: Vec<Vec<Option<BlockID>>>,
+    pub piece: Option<FumenPiece>,
+    pub comment: String,
+}
+
+impl FumenPage {
+    /// The playfield, falling block and queue of the game as a page
+    pub fn from_game(game: &Tetris) -> Result<Self, FumenError> {
+        if game.width() != FIELD_WIDTH as i32 {
+            return Err(FumenError::UnsupportedWidth(game.width()))
+        }
+        if game.height() > FIELD_TOP_V115 as i32 {
+            return Err(FumenError::UnsupportedHeight(game.height()))
+        }
+        if *game.pieces() != PieceSet::tetrominoes() {
+            return Err(FumenError::UnsupportedPieces(game.pieces().name.clone()))
+        }
+
+        let mut field: Vec<Vec<Option<BlockID>>> = game.playfield().into_iter().rev().collect();
+        field.resize(FIELD_TOP_V115, vec![None; FIELD_WIDTH]);
+
+        let current = game.current_block();
+        let cells: Vec<(i32, i32)> = current.config().map(|c| (c.0 - 1, game.height() - 1 - c.1)).collect();
+        // A block high up in the buffer above the board is out of the fumen field
+        if cells.iter().any(|&(_, y)| y >= FIELD_TOP_V115 as i32) {
+            return Err(FumenError::InvalidPiece)
+        }
+        let piece = FumenPiece::from_cells(*current.id(), &cells);
+
+        let queue: String = game.peek_queue(QUEUE_LEN).iter().filter_map(|b| b.id().to_char()).collect();
+        let hold: String = game.held_block().and_then(|b| b.id().to_char()).into_iter().collect();
+        let comment = format!("{}[{}]({}){}", QUIZ_PREFIX, hold, current.id().to_char().unwrap_or_default(), queue);
+
+        Ok(Self { field, piece, comment })
+    }
+
+    /// Sets up the page in a fresh game, which keeps its rules and rotation system.
+    /// The quiz comment (if any) decides the hold and the queue
+    pub fn apply_to(&self, game: &mut Tetris) -> Result<(), FumenError> {
+        if game.width() != FIELD_WIDTH as i32 {
+            return Err(FumenError::UnsupportedWidth(game.width()))
+        }
+        if *game.pieces() != PieceSet::tetrominoes() {
+            return Err(FumenError::UnsupportedPieces(game.pieces().name.clone()))
+        }
+        let height = game.height();
+
+        // Rows above the board are kept as long as they fit inside the walls
+        let rows = (height as usize + 2).min(self.field.len());
+        let field: Vec<Vec<Option<BlockID>>> = self.field[..rows].iter().rev().cloned().collect();
+        game.set_playfield(&field);
+
+        let quiz = self.comment.strip_prefix(QUIZ_PREFIX).map(parse_quiz);
+        if let Some((hold, current, queue)) = &quiz {
+            if game.rules().has_hold() {
+                game.set_held_block(*hold);
+            }
+            game.set_queue(queue);
+            if let Some(current) = current {
+                game.spawn_current_block(*current);
+            }
+        }
+
+        if let Some(piece) = &self.piece {
+            let coords: Vec<Coord> = piece.cells().iter().map(|&(x, y)| Coord(x + 1, height - 1 - y)).collect();
+            let block = block_covering(game, piece.id, &coords).ok_or(FumenError::InvalidPiece)?;
+            if !game.is_feasible(&block) {
+                return Err(FumenError::InvalidPiece)
+            }
+            game.set_current_block(block);
+        }
+
+        Ok(())
+    }
+}
+
+impl FumenPiece {
+    /// Cells (x, y) covered by the piece
+    pub fn cells(&self) -> Vec<(i32, i32)> {
+        piece_offsets(self.id).iter()
+            .map(|&(dx, dy)| match self.rotation {
+                FumenRotation::Spawn => (dx, dy),
+                FumenRotation::Right => (dy, -dx),
+                FumenRotation::Reverse => (-dx, -dy),
+                FumenRotation::Left => (-dy, dx),
+            })
+            .map(|(dx, dy)| (self.x + dx, self.y + dy))
+            .collect()
+    }
+
+    // Finds the piece that covers exactly `cells`
+    fn from_cells(id: BlockID, cells: &[(i32, i32)]) -> Option<Self> {
+        let mut sorted = cells.to_vec();
+        sorted.sort();
+        let &(x0, y0) = cells.first()?;
+
+        [FumenRotation::Spawn, FumenRotation::Right, FumenRotation::Reverse, FumenRotation::Left].iter()
+            .flat_map(|&rotation| {
+                // Any cell of the piece may be the one at (x0, y0)
+                let origin = FumenPiece { id, rotation, x: 0, y: 0 };
+                origin.cells().into_iter().map(move |(dx, dy)| FumenPiece { id, rotation, x: x0 - dx, y: y0 - dy })
+            })
+            .find(|piece| {
+                let mut covered = piece.cells();
+                covered.sort();
+                covered == sorted
+            })
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Decoding
+
+/// Decodes all pages of a fumen string, e.g., "v115@vhAAgH"
+pub fn decode(fumen: &str) -> Result<Vec<FumenPage>, FumenError> {
+    let fumen = fumen.trim();
+    let fumen = match fumen.find('@') {
+        // The version is the four characters before '@', anything else in front of it is ignored
+        Some(i) => fumen.get(i.saturating_sub(4)..).ok_or(FumenError::UnsupportedVersion)?,
+        None => fumen,
+    };
+    let (version, data) = fumen.split_once('@').ok_or(FumenError::UnsupportedVersion)?;
+    let field_top = match version.get(1..) {
+        Some("115") => FIELD_TOP_V115,
+        Some("110") => FIELD_TOP_V110,
+        _ => return Err(FumenError::UnsupportedVersion),
+    };
+
+    let mut values = Values::new(data)?;
+    let num_cells = (field_top + 1) * FIELD_WIDTH;
+
+    // The field includes the garbage row below the board, which is the first row of `field`
+    let mut field = vec![0u32; num_cells];
+    let mut pages = vec![];
+    let mut repeat_count = 0;
+    let mut comment = String::new();
+
+    while !values.is_empty() {
+        // Field, encoded as run lengths of differences to the previous page
+        if repeat_count > 0 {
+            repeat_count -= 1;
+        } else {
+            let mut index = 0;
+            let mut changed = true;
+            while index < num_cells {
+                let value = values.poll(2)?;
+                let diff = value / num_cells as u32;
+                let count = value as usize % num_cells + 1;
+                if diff == 8 && count == num_cells {
+                    changed = false;
+                }
+                for _ in 0..count {
+                    let cell = field.get_mut(cell_index(index, field_top)).ok_or(FumenError::InvalidValue(value))?;
+                    *cell = (*cell + diff).checked_sub(8).filter(|&v| v <= 8).ok_or(FumenError::InvalidValue(value))?;
+                    index += 1;
+                }
+            }
+            if !changed {
+                repeat_count = values.poll(1)?;
+            }
+        }
+
+        // Action, i.e., the piece and flags of the page
+        let mut action = values.poll(3)?;
+        let piece_type = action % 8;
+        action /= 8;
+        let rotation = action % 4;
+        action /= 4;
+        let position = action % num_cells as u32;
+        action /= num_cells as u32;
+        let rise = action % 2 == 1;
+        let mirror = (action / 2) % 2 == 1;
+        let has_comment = (action / 8) % 2 == 1;
+        let lock = (action / 16) % 2 == 0;
+
+        if has_comment {
+            let length = values.poll(2)? as usize;
+            let mut escaped = String::with_capacity(length);
+            for _ in 0..length.div_ceil(4) {
+                let mut value = values.poll(5)?;
+                for _ in 0..4 {
+                    let c = value % COMMENT_CHAR_VALUES;
+                    escaped.push(*COMMENT_TABLE.get(c as usize).ok_or(FumenError::InvalidValue(c))? as char);
+                    value /= COMMENT_CHAR_VALUES;
+                }
+            }
+            escaped.truncate(length);
+            comment = unescape(&escaped);
+        }
+
+        let piece = match id_from_number(piece_type) {
+            Some(BlockID::G) | None => None,
+            Some(id) => {
+                let rotation = rotation_from_number(rotation);
+                let (x, y) = decode_position(position as i32, id, rotation, field_top);
+                Some(FumenPiece { id, rotation, x, y })
+            },
+        };
+
+        pages.push(FumenPage {
+            field: (0..field_top)
+                .map(|y| (0..FIELD_WIDTH).map(|x| id_from_number(field[(y + 1) * FIELD_WIDTH + x])).collect())
+                .collect(),
+            piece,
+            comment: comment.clone(),
+        });
+
+        // Locking the piece changes the field that the next page is based on
+        if lock {
+            if let Some(piece) = piece {
+                lock_piece(&mut field, &piece);
+            }
+            clear_full_lines(&mut field);
+            if rise {
+                field.rotate_right(FIELD_WIDTH);
+                field[..FIELD_WIDTH].fill(0);
+            }
+            if mirror {
+                field.chunks_mut(FIELD_WIDTH).skip(1).for_each(|row| row.reverse());
+            }
+        }
+    }
+
+    Ok(pages)
+}
+
+// Data is stored from the top left corner, with the garbage row last
+fn cell_index(index: usize, field_top: usize) -> usize {
+    let y = field_top - index / FIELD_WIDTH;
+    y * FIELD_WIDTH + index % FIELD_WIDTH
+}
+
+fn lock_piece(field: &mut [u32], piece: &FumenPiece) {
+    for (x, y) in piece.cells() {
+        if (0..FIELD_WIDTH as i32).contains(&x) && y >= 0 {
+            if let Some(cell) = field.get_mut((y as usize + 1) * FIELD_WIDTH + x as usize) {
+                *cell = number_from_id(&piece.id);
+            }
+        }
+    }
+}
+
+fn clear_full_lines(field: &mut Vec<u32>) {
+    let garbage: Vec<u32> = field[..FIELD_WIDTH].to_vec();
+    let mut rows: Vec<Vec<u32>> = field[FIELD_WIDTH..].chunks(FIELD_WIDTH)
+        .filter(|row| row.contains(&0))
+        .map(|row| row.to_vec())
+        .collect();
+    rows.resize(field.len() / FIELD_WIDTH - 1, vec![0; FIELD_WIDTH]);
+    *field = garbage.into_iter().chain(rows.into_iter().flatten()).collect();
+}
+
+// The fumen position is not always the rotation center, see the reference implementation `tetris-fumen`
+fn decode_position(position: i32, id: BlockID, rotation: FumenRotation, field_top: usize) -> (i32, i32) {
+    let (x, y) = (position % FIELD_WIDTH as i32, field_top as i32 - position / FIELD_WIDTH as i32 - 1);
+    let (dx, dy) = position_correction(id, rotation);
+    (x + dx, y + dy)
+}
+
+fn encode_position(piece: &FumenPiece, field_top: usize) -> u32 {
+    let (dx, dy) = position_correction(piece.id, piece.rotation);
+    let (x, y) = (piece.x - dx, piece.y - dy);
+    ((field_top as i32 - y - 1) * FIELD_WIDTH as i32 + x) as u32
+}
+
+fn position_correction(id: BlockID, rotation: FumenRotation) -> (i32, i32) {
+    match (id, rotation) {
+        (BlockID::O, FumenRotation::Left) => (1, -1),
+        (BlockID::O, FumenRotation::Reverse) => (1, 0),
+        (BlockID::O, FumenRotation::Spawn) => (0, -1),
+        (BlockID::I, FumenRotation::Reverse) => (1, 0),
+        (BlockID::I, FumenRotation::Left) => (0, -1),
+        (BlockID::S, FumenRotation::Spawn) => (0, -1),
+        (BlockID::S, FumenRotation::Right) => (-1, 0),
+        (BlockID::Z, FumenRotation::Spawn) => (0, -1),
+        (BlockID::Z, FumenRotation::Left) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Encoding
+
+/// Encodes the pages as a v115 fumen string
+pub fn encode(pages: &[FumenPage]) -> String {
+    let num_cells = (FIELD_TOP_V115 + 1) * FIELD_WIDTH;
+    let mut data = String::new();
+    let mut prev = vec![0u32; num_cells];
+    let mut prev_comment = String::new();
+    // Where the repeat count of the last unchanged field is stored, if the previous page had one
+    let mut repeat_at: Option<usize> = None;
+
+    for page in pages {
+        let mut field = vec![0u32; num_cells];
+        for (y, row) in page.field.iter().take(FIELD_TOP_V115).enumerate() {
+            for (x, cell) in row.iter().take(FIELD_WIDTH).enumerate() {
+                field[(y + 1) * FIELD_WIDTH + x] = cell.as_ref().map_or(0, number_from_id);
+            }
+        }
+
+        // Run lengths of the difference to the previous page, from the top left corner
+        let diffs: Vec<u32> = (0..num_cells)
+            .map(|index| cell_index(index, FIELD_TOP_V115))
+            .map(|j| field[j] + 8 - prev[j])
+            .collect();
+        if diffs.iter().all(|&diff| diff == 8) {
+            // Consecutive unchanged fields are stored once together with how many pages repeat it
+            match repeat_at {
+                Some(at) if data.as_bytes()[at] != ENCODING_TABLE[63] => {
+                    let count = ENCODING_TABLE.iter().position(|&c| c == data.as_bytes()[at]).unwrap_or(0);
+                    data.replace_range(at..at + 1, &(ENCODING_TABLE[count + 1] as char).to_string());
+                },
+                _ => {
+                    push_value(&mut data, 8 * num_cells as u32 + num_cells as u32 - 1, 2);
+                    repeat_at = Some(data.len());
+                    push_value(&mut data, 0, 1);
+                },
+            }
+        } else {
+            repeat_at = None;
+            let mut run = (diffs[0], 0);
+            for &diff in &diffs[1..] {
+                if diff == run.0 {
+                    run.1 += 1;
+                } else {
+                    push_value(&mut data, run.0 * num_cells as u32 + run.1, 2);
+                    run = (diff, 0);
+                }
+            }
+            push_value(&mut data, run.0 * num_cells as u32 + run.1, 2);
+        }
+
+        let has_comment = page.comment != prev_comment;
+        let (piece_type, rotation, position) = match &page.piece {
+            Some(piece) => (number_from_id(&piece.id), number_from_rotation(piece.rotation), encode_position(piece, FIELD_TOP_V115)),
+            None => (0, 0, 0),
+        };
+        let mut action = 0;                                 // lock
+        action = action * 2 + has_comment as u32;
+        action = action * 2 + 1;                            // colorize
+        action *= 2;                                        // mirror
+        action *= 2;                                        // rise
+        action = action * num_cells as u32 + position;
+        action = action * 4 + rotation;
+        action = action * 8 + piece_type;
+        push_value(&mut data, action, 3);
+
+        if has_comment {
+            let escaped: Vec<u32> = escape(&page.comment).bytes()
+                .map(|b| COMMENT_TABLE.iter().position(|&c| c == b).unwrap_or(0) as u32)
+                .collect();
+            push_value(&mut data, escaped.len() as u32, 2);
+            for chunk in escaped.chunks(4) {
+                let value = chunk.iter().rev().fold(0, |acc, &c| acc * COMMENT_CHAR_VALUES + c);
+                push_value(&mut data, value, 5);
+            }
+            prev_comment = page.comment.clone();
+        }
+
+        // The next page is based on the field after the piece has locked
+        if let Some(piece) = &page.piece {
+            lock_piece(&mut field, piece);
+        }
+        clear_full_lines(&mut field);
+        prev = field;
+    }
+
+    // Long fumens are split by '?' in the same places as the reference implementation does
+    let mut fumen = String::from("v115@");
+    if data.len() <= 42 {
+        fumen.push_str(&data);
+    } else {
+        fumen.push_str(&data[..42]);
+        for chunk in data.as_bytes()[42..].chunks(47) {
+            fumen.push('?');
+            fumen.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        }
+    }
+    fumen
+}
+
+fn push_value(data: &mut String, mut value: u32, digits: usize) {
+    for _ in 0..digits {
+        data.push(ENCODING_TABLE[(value % 64) as usize] as char);
+        value /= 64;
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+// Reads digits from the data, ignoring the '?' line breaks
+struct Values {
+    digits: Vec<u32>,
+    pos: usize,
+}
+
+impl Values {
+    fn new(data: &str) -> Result<Self, FumenError> {
+        let digits = data.chars()
+            .filter(|&c| c != '?')
+            .map(|c| ENCODING_TABLE.iter()
+                .position(|&e| e as char == c)
+                .map(|v| v as u32)
+                .ok_or(FumenError::InvalidCharacter(c)))
+            .collect::<Result<Vec<u32>, FumenError>>()?;
+        Ok(Self { digits, pos: 0 })
+    }
+
+    fn poll(&mut self, digits: usize) -> Result<u32, FumenError> {
+        let slice = self.digits.get(self.pos..self.pos + digits).ok_or(FumenError::UnexpectedEnd)?;
+        self.pos += digits;
+        Ok(slice.iter().rev().fold(0, |acc, &d| acc * 64 + d))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.digits.len()
+    }
+}
+
+// Fumen numbers its pieces differently than `BlockID`
+fn id_from_number(n: u32) -> Option<BlockID> {
+    match n {
+        1 => Some(BlockID::I),
+        2 => Some(BlockID::L),
+        3 => Some(BlockID::O),
+        4 => Some(BlockID::Z),
+        5 => Some(BlockID::T),
+        6 => Some(BlockID::J),
+        7 => Some(BlockID::S),
+        8 => Some(BlockID::G),
+        _ => None,
+    }
+}
+
+fn number_from_id(id: &BlockID) -> u32 {
+    match *id {
+        BlockID::I => 1,
+        BlockID::L => 2,
+        BlockID::O => 3,
+        BlockID::Z => 4,
+        BlockID::T => 5,
+        BlockID::J => 6,
+        BlockID::S => 7,
+        _ => 8,
+    }
+}
+
+fn rotation_from_number(n: u32) -> FumenRotation {
+    match n {
+        0 => FumenRotation::Reverse,
+        1 => FumenRotation::Right,
+        2 => FumenRotation::Spawn,
+        _ => FumenRotation::Left,
+    }
+}
+
+fn number_from_rotation(rotation: FumenRotation) -> u32 {
+    match rotation {
+        FumenRotation::Reverse => 0,
+        FumenRotation::Right => 1,
+        FumenRotation::Spawn => 2,
+        FumenRotation::Left => 3,
+    }
+}
+
+// Cells of each piece in spawn orientation relative to the rotation center (y pointing up)
+fn piece_offsets(id: BlockID) -> [(i32, i32); 4] {
+    match id {
+        BlockID::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        BlockID::T => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        BlockID::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        BlockID::L => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        BlockID::J => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        BlockID::S => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        BlockID::Z => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+        _ => [(0, 0); 4],
+    }
+}
+
+// Rotates (by the rotation system of the game) and moves a freshly spawned block until it covers `coords`
+fn block_covering(game: &Tetris, id: BlockID, coords: &[Coord]) -> Option<Block> {
+    let mut target = coords.to_vec();
+    target.sort();
+    for orientation in Orientation::ALL {
+        let block = game.rotation().oriented(game.pieces(), id, orientation);
+        let mut cells: Vec<Coord> = block.config().copied().collect();
+        cells.sort();
+        let offset = Coord(target[0].0 - cells[0].0, target[0].1 - cells[0].1);
+        let moved = &block + offset;
+        let mut moved_cells: Vec<Coord> = moved.config().copied().collect();
+        moved_cells.sort();
+        if moved_cells == target {
+            return Some(moved)
+        }
+    }
+    None
+}
+
+// "[H](C)QUEUE" into hold, current and queue
+fn parse_quiz(quiz: &str) -> (Option<BlockID>, Option<BlockID>, Vec<BlockID>) {
+    let between = |open: char, close: char| quiz.find(open)
+        .and_then(|i| quiz[i + 1..].find(close).map(|j| &quiz[i + 1..i + 1 + j]))
+        .unwrap_or("");
+    let hold = between('[', ']').chars().find_map(BlockID::from_char);
+    let current = between('(', ')').chars().find_map(BlockID::from_char);
+    let queue = quiz.rfind(')').map_or("", |i| &quiz[i + 1..]).chars().filter_map(BlockID::from_char).collect();
+    (hold, current, queue)
+}
+
+// Comments are stored with JavaScript's `escape` applied
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '@' | '*' | '_' | '+' | '-' | '.' | '/' => c.to_string(),
+            c if (c as u32) < 256 => format!("%{:02X}", c as u32),
+            c => format!("%u{:04X}", c as u32),
+        })
+        .collect()
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('%') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let (digits, len) = if rest.starts_with('u') { (rest.get(1..5), 5) } else { (rest.get(..2), 2) };
+        match digits.and_then(|d| u32::from_str_radix(d, 16).ok()).and_then(char::from_u32) {
+            Some(c) => { result.push(c); rest = &rest[len..]; },
+            None => result.push('%'),
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_field() -> Vec<Vec<Option<BlockID>>> {
+        vec![vec![None; FIELD_WIDTH]; FIELD_TOP_V115]
+    }
+
+    // Every other cell of the first `n` in the bottom rows filled, and a comment of `len` characters
+    fn page(n: usize, len: usize) -> FumenPage {
+        let mut field = empty_field();
+        for i in (0..n).step_by(2) {
+            field[i / FIELD_WIDTH][i % FIELD_WIDTH] = Some(BlockID::G);
+        }
+        FumenPage { field, piece: None, comment: "a".repeat(len) }
+    }
+
+    #[test]
+    fn encodes_empty_page() {
+        let page = FumenPage { field: empty_field(), piece: None, comment: String::new() };
+        assert_eq!(encode(&[page]), "v115@vhAAgH");
+    }
+
+    #[test]
+    fn decodes_empty_page() {
+        let page = FumenPage { field: empty_field(), piece: None, comment: String::new() };
+        assert_eq!(decode("v115@vhAAgH"), Ok(vec![page.clone()]));
+        assert_eq!(decode("https://fumen.zui.jp/?v115@vhAAgH"), Ok(vec![page]));
+    }
+
+    #[test]
+    fn rejects_multibyte_version() {
+        assert_eq!(decode("éabc@x"), Err(FumenError::UnsupportedVersion));
+        assert_eq!(decode("é@vhAAgH"), Err(FumenError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn round_trips_around_line_break_length() {
+        let mut lengths = vec![];
+        for n in 0..=2 * FIELD_WIDTH {
+            for len in 0..=12 {
+                let page = page(n, len);
+                let fumen = encode(std::slice::from_ref(&page));
+                lengths.push(fumen.replace('?', "").len() - "v115@".len());
+                assert_eq!(decode(&fumen), Ok(vec![page]), "{}", fumen);
+            }
+        }
+        // The first line break comes after 42 characters
+        for len in [41, 42] {
+            assert!(lengths.contains(&len), "no page encodes to {} characters", len);
+        }
+        assert!(lengths.iter().any(|&len| len > 42));
+    }
+    #[test]
+    fn applies_to_game_of_any_rules() {
+        use crate::enums::Ruleset;
+        use crate::rotation::RotationSystem;
+
+        let mut game = Tetris::new(FIELD_WIDTH as i32, 20, 1);
+        game.set_rotation(RotationSystem::Ars);
+        let page = FumenPage::from_game(&game).unwrap();
+
+        let mut other = Tetris::new(FIELD_WIDTH as i32, 20, 2);
+        other.set_rules(Ruleset::Nes);
+        other.set_rotation(RotationSystem::Ars);
+        page.apply_to(&mut other).unwrap();
+        assert_eq!(other.rules(), Ruleset::Nes);
+        assert_eq!(other.rotation(), RotationSystem::Ars);
+        let cells = |game: &Tetris| {
+            let mut cells: Vec<Coord> = game.current_block().config().copied().collect();
+            cells.sort();
+            cells
+        };
+        assert_eq!(cells(&other), cells(&game));
+
+        let mut narrow = Tetris::new(8, 20, 3);
+        assert_eq!(page.apply_to(&mut narrow), Err(FumenError::UnsupportedWidth(8)));
+    }
+    #[test]
+    fn rejects_boards_taller_than_the_field() {
+        for height in [24, 30, 40] {
+            let game = Tetris::new(FIELD_WIDTH as i32, height, 1);
+            assert_eq!(FumenPage::from_game(&game), Err(FumenError::UnsupportedHeight(height)));
+        }
+        // The block spawns above the board, which is above the field of a board as high as it
+        let game = Tetris::new(FIELD_WIDTH as i32, FIELD_TOP_V115 as i32, 1);
+        assert_eq!(FumenPage::from_game(&game), Err(FumenError::InvalidPiece));
+        let game = Tetris::new(FIELD_WIDTH as i32, 21, 1);
+        let page = FumenPage::from_game(&game).unwrap();
+        assert_eq!(decode(&encode(std::slice::from_ref(&page))), Ok(vec![page]));
+    }
+    #[test]
+    fn games_round_trip_through_fumen() {
+        use crate::enums::GameCmd;
+
+        let mut game = Tetris::new(FIELD_WIDTH as i32, 20, 9);
+        for cmd in [GameCmd::HardDrop, GameCmd::Hold, GameCmd::HardDrop, GameCmd::HardDrop] {
+            game.execute(&cmd);
+        }
+        let pages = decode(&encode(&[FumenPage::from_game(&game).unwrap()])).unwrap();
+
+        let mut imported = Tetris::new(FIELD_WIDTH as i32, 20, 1);
+        pages[0].apply_to(&mut imported).unwrap();
+        assert_eq!(imported.playfield(), game.playfield());
+        assert_eq!(imported.current_block().id(), game.current_block().id());
+        assert_eq!(imported.held_block().map(|b| *b.id()), game.held_block().map(|b| *b.id()));
+        let queue = |game: &Tetris| game.peek_queue(QUEUE_LEN).iter().map(|b| *b.id()).collect::<Vec<_>>();
+        assert_eq!(queue(&imported), queue(&game));
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+/// Bounded undo/redo stacks of snapshots, the oldest snapshots are forgotten first
+#[derive(Clone)]
+pub struct History<T: Clone> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+    capacity: usize,
+}
+
+impl<T: Clone> History<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { undo: VecDeque::with_capacity(capacity), redo: vec![], capacity }
+    }
+
+    /// Remember the state from before a change, which invalidates everything that could be redone
+    pub fn record(&mut self, snapshot: T) {
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(snapshot);
+        self.redo.clear();
+    }
+
+    /// The state to return to, `current` is kept so the undo can be redone
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let snapshot = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(snapshot)
+    }
+
+    /// The state that was undone last, `current` is kept so the redo can be undone
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let snapshot = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(snapshot)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_the_oldest_snapshots() {
+        let mut history = History::new(2);
+        for snapshot in 0..3 {
+            history.record(snapshot);
+        }
+        assert_eq!(history.undo(3), Some(2));
+        assert_eq!(history.undo(2), Some(1));
+        assert_eq!(history.undo(1), None);
+        assert_eq!(history.redo(1), Some(2));
+        assert_eq!(history.redo(2), Some(3));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn recording_drops_the_redo() {
+        let mut history = History::new(10);
+        history.record(0);
+        assert_eq!(history.undo(1), Some(0));
+        assert!(history.can_redo());
+        history.record(0);
+        assert!(!history.can_redo());
+        assert!(history.can_undo());
+    }
+}
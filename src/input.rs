@@ -0,0 +1,186 @@
+use serde::{Serialize, Deserialize};
+
+use super::enums::GameCmd;
+use super::timer::FRAME_RATE;
+
+// Auto repeat that fell this far behind (e.g., after a pause) starts over instead of catching up
+const MAX_REPEAT_LAG_MS: i64 = 500;
+
+/// What the delays of a `Handling` count, the times given to `Controls` have to be in the same unit
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeUnit {
+    Milliseconds,
+    // Logical frames of the frame clock, see `FrameClock`
+    Frames,
+}
+
+/// How held keys repeat
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Handling {
+    // Delayed auto shift, a held shift key starts repeating after this long
+    pub das: i64,
+    // Auto repeat rate, time between the repeated shifts
+    pub arr: i64,
+    // Time between the rows of a held soft drop
+    pub soft_drop: i64,
+    pub unit: TimeUnit,
+}
+
+impl Handling {
+    /// The NES: 16 frames before a held shift repeats, then a shift every 6 frames, and a row every other frame
+    pub const NES: Handling = Handling { das: 16, arr: 6, soft_drop: 2, unit: TimeUnit::Frames };
+}
+
+impl Default for Handling {
+    fn default() -> Self {
+        Self { das: 167, arr: 33, soft_drop: 33, unit: TimeUnit::Milliseconds }
+    }
+}
+
+// A command whose key is down, and when it repeats next (if it repeats at all)
+#[derive(Debug, Copy, Clone)]
+struct Held {
+    cmd: GameCmd,
+    next: Option<i64>,
+}
+
+/// Turns key presses and releases into game commands, in the order they happened.
+/// Every press is a command of its own, held shifts and soft drops repeat as set by `Handling`
+#[derive(Debug, Clone, Default)]
+pub struct Controls {
+    pub handling: Handling,
+    // In the order the keys went down, the last shift held is the one that repeats
+    held: Vec<Held>,
+}
+
+impl Controls {
+    pub fn new(handling: Handling) -> Self {
+        Self { handling, held: vec![] }
+    }
+
+    /// The key of `cmd` went down (or up) at `time` (in the unit of the handling),
+    /// returns the command to execute right away
+    pub fn key(&mut self, cmd: GameCmd, pressed: bool, time: i64) -> Option<GameCmd> {
+        self.held.retain(|held| held.cmd != cmd);
+        if !pressed {
+            // The shift held before takes over, and has to charge again
+            let das = self.handling.das;
+            if let (GameCmd::Shift(_), Some(held)) = (cmd, self.active_shift()) {
+                held.next = Some(time + das);
+            }
+            return None
+        }
+
+        let next = match cmd {
+            GameCmd::Shift(_) => Some(time + self.handling.das),
+            GameCmd::SoftDrop => Some(time + self.handling.soft_drop),
+            _ => None,
+        };
+        self.held.push(Held { cmd, next });
+        Some(cmd)
+    }
+
+    /// Commands repeated by held keys up to `time`, with the time each one was due
+    pub fn repeats(&mut self, time: i64) -> Vec<(i64, GameCmd)> {
+        let shift = self.held.iter().rposition(|held| matches!(held.cmd, GameCmd::Shift(_)));
+        let handling = self.handling;
+        let max_lag = match handling.unit {
+            TimeUnit::Milliseconds => MAX_REPEAT_LAG_MS,
+            TimeUnit::Frames => MAX_REPEAT_LAG_MS * FRAME_RATE / 1000,
+        };
+        let mut repeats = vec![];
+        for (i, held) in self.held.iter_mut().enumerate() {
+            let period = match held.cmd {
+                GameCmd::Shift(_) if Some(i) == shift => handling.arr,
+                GameCmd::SoftDrop => handling.soft_drop,
+                _ => continue,
+            };
+            let Some(next) = &mut held.next else { continue };
+            if *next < time - max_lag {
+                *next = time;
+            }
+            while *next <= time {
+                repeats.push((*next, held.cmd));
+                *next += period.max(1);
+            }
+        }
+        repeats.sort_by_key(|&(time, _)| time);
+        repeats
+    }
+
+    /// When (in the unit of the handling) a held key repeats next, if one does
+    pub fn next_repeat(&self) -> Option<i64> {
+        self.held.iter().filter_map(|held| held.next).min()
+    }
+
+    /// Whether the key of `cmd` is down
+    pub fn is_held(&self, cmd: &GameCmd) -> bool {
+        self.held.iter().any(|held| held.cmd == *cmd)
+    }
+
+    /// Commands whose keys are down, last pressed first
+    pub fn held(&self) -> impl Iterator<Item=GameCmd> + '_ {
+        self.held.iter().rev().map(|held| held.cmd)
+    }
+
+    /// Forgets the keys that are down, e.g., when the game loses the keyboard
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    fn active_shift(&mut self) -> Option<&mut Held> {
+        self.held.iter_mut().rev().find(|held| matches!(held.cmd, GameCmd::Shift(_)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::ShiftCmd;
+
+    #[test]
+    fn nes_das_repeats_on_frames() {
+        let shift = GameCmd::Shift(ShiftCmd::Right);
+        let mut controls = Controls::new(Handling::NES);
+        assert_eq!(controls.key(shift, true, 100), Some(shift));
+        let repeats: Vec<i64> = (100..=130).flat_map(|frame| controls.repeats(frame)).map(|(time, _)| time).collect();
+        assert_eq!(repeats, vec![116, 122, 128]);
+    }
+    #[test]
+    fn shifts_repeat_after_das_at_arr() {
+        let left = GameCmd::Shift(ShiftCmd::Left);
+        let mut controls = Controls::new(Handling { das: 100, arr: 20, soft_drop: 10, unit: TimeUnit::Milliseconds });
+        assert_eq!(controls.key(left, true, 0), Some(left));
+        assert!(controls.repeats(99).is_empty());
+        assert_eq!(controls.repeats(141), [(100, left), (120, left), (140, left)]);
+        assert_eq!(controls.next_repeat(), Some(160));
+        assert_eq!(controls.key(GameCmd::HardDrop, true, 150), Some(GameCmd::HardDrop));
+        assert_eq!(controls.repeats(160), [(160, left)]);
+    }
+
+    #[test]
+    fn last_shift_held_repeats_and_the_other_takes_over_after_das() {
+        let (left, right) = (GameCmd::Shift(ShiftCmd::Left), GameCmd::Shift(ShiftCmd::Right));
+        let mut controls = Controls::new(Handling { das: 100, arr: 20, soft_drop: 10, unit: TimeUnit::Milliseconds });
+        controls.key(left, true, 0);
+        controls.key(right, true, 50);
+        assert_eq!(controls.repeats(150), [(150, right)]);
+        assert_eq!(controls.held().collect::<Vec<_>>(), [right, left]);
+
+        assert_eq!(controls.key(right, false, 160), None);
+        assert!(controls.repeats(259).is_empty());
+        assert_eq!(controls.repeats(260), [(260, left)]);
+    }
+
+    #[test]
+    fn repeats_start_over_after_a_long_lag() {
+        let down = GameCmd::SoftDrop;
+        let mut controls = Controls::new(Handling::default());
+        controls.key(down, true, 0);
+        assert_eq!(controls.repeats(10_000), [(10_000, down)]);
+        controls.release_all();
+        assert!(!controls.is_held(&down));
+        assert!(controls.repeats(20_000).is_empty());
+    }
+}
+
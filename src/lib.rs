@@ -3,32 +3,71 @@
 mod util;
 mod enums;
 mod block;
+mod pieces;
+mod rotation;
 mod tetris;
 mod scoreboard;
 mod timer;
+mod save;
+mod events;
+mod history;
+mod fumen;
+mod bot;
+mod finesse;
+mod stats;
+mod theme;
+mod replay;
+mod options;
+mod audio;
+mod input;
+mod env;
 mod app;
 
-pub use tetris::Tetris;
+#[cfg(feature = "python")]
+mod python;
+
+pub use tetris::{Tetris, LockInfo, Delays, InitialInput, BoardSizeError, BUFFER_HEIGHT, MIN_SIZE, MAX_SIZE};
+pub use scoreboard::Scoreboard;
+pub use save::{SaveGame, SAVE_VERSION};
+pub use events::{GameEvent, GameObserver};
+pub use history::History;
+pub use fumen::{FumenPage, FumenPiece, FumenRotation, FumenError, decode as decode_fumen, encode as encode_fumen};
+pub use timer::{TickTimer, FrameClock, START_PERIOD, MIN_PERIOD, LVL_UP, FRAME_RATE};
 pub use app::TetrisApp;
+pub use bot::{Bot, Heuristic, Placement};
+pub use finesse::{Finesse, FinesseResult, FinesseStats};
+pub use stats::{GameStats, SessionStats};
+pub use theme::{Theme, SkinError};
+pub use pieces::{Piece, PieceSet, PieceSetError};
+pub use rotation::{RotationSystem, Orientation};
+pub use replay::{Replay, ReplayAction, Playback, ReplayError, REPLAY_VERSION};
+pub use options::{Options, OptionsError, USAGE, MAX_LEVEL};
+pub use audio::{Audio, AudioSettings, Sound};
+pub use input::{Controls, Handling, TimeUnit};
+pub use env::{Env, EnvConfig, EnvError, ActionSpace, Observation, Info, KEY_ACTIONS};
+pub use enums::{BlockID, ShiftCmd, RotateCmd, GameCmd, GameMode, GameStatus, TopOut, Phase, Ruleset};
 
 ////////////
 // Native //
 ////////////
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn start_native(canvas_id: &str, width: i32, height: i32) -> Result<(), eframe::Error> {
+pub fn start_native(canvas_id: &str, options: Options) -> Result<(), eframe::Error> {
+    let (width, height) = options.board_size();
+
     // Options
     let native_options = eframe::NativeOptions {
         initial_window_size: Some(
             egui::Vec2::new(
                 app::CELL_SIZE * (width as f32 + 2.0) + app::SIDEPANEL_WIDTH,
-                app::CELL_SIZE * (height as f32 + 0.5)
+                app::CELL_SIZE * (height as f32 + 0.5 + app::BUFFER_PEEK)
             )
         ),
         resizable: false,
         mouse_passthrough: true,
         always_on_top: true,
         centered: true,
+        fullscreen: options.fullscreen,
 
         ..Default::default()
     };
@@ -37,7 +76,7 @@ pub fn start_native(canvas_id: &str, width: i32, height: i32) -> Result<(), efra
     eframe::run_native(
         canvas_id,
         native_options,
-        Box::new(move |cc| Box::new(app::TetrisApp::new(cc, width, height))),
+        Box::new(move |cc| Box::new(app::TetrisApp::new(cc, options))),
     )
 }
 
@@ -62,26 +101,179 @@ impl WebHandle {
         let mut app = self.handle.lock();
         app.destroy()
     }
+
+    /// The settings of new games as URL query parameters, e.g., `w=10&h=20&mode=marathon&level=1`
+    #[wasm_bindgen]
+    pub fn options(&self) -> String {
+        self.with_app(|app, _| app.options().to_query())
+    }
+
+    /// Changes one setting (named like the URL query parameters) and starts a new game with it
+    #[wasm_bindgen]
+    pub fn set_option(&self, name: &str, value: &str) -> Result<(), wasm_bindgen::JsValue> {
+        self.with_app(|app, ctx| {
+            let mut options = app.options();
+            options.set(name, value)
+                .and_then(|()| options.validate())
+                .map(|options| app.set_options(ctx, options))
+                .map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Starts a new game set up by URL query parameters, e.g., `mode=practice&seed=3`, settings that are left out keep their defaults
+    #[wasm_bindgen]
+    pub fn reset(&self, options: &str) -> Result<(), wasm_bindgen::JsValue> {
+        let options = parse_options(options)?;
+        self.with_app(|app, ctx| app.set_options(ctx, options));
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn pause(&self) {
+        self.with_app(|app, _| app.set_paused(true));
+    }
+
+    #[wasm_bindgen]
+    pub fn resume(&self) {
+        self.with_app(|app, _| app.set_paused(false));
+    }
+
+    #[wasm_bindgen]
+    pub fn is_paused(&self) -> bool {
+        self.with_app(|app, _| app.is_paused())
+    }
+
+    #[wasm_bindgen]
+    pub fn score(&self) -> usize {
+        self.with_app(|app, _| app.score())
+    }
+
+    #[wasm_bindgen]
+    pub fn level(&self) -> usize {
+        self.with_app(|app, _| app.level())
+    }
+
+    #[wasm_bindgen]
+    pub fn lines(&self) -> usize {
+        self.with_app(|app, _| app.lines())
+    }
+
+    /// Sends keyboard input to this game instead of the other games on the page
+    #[wasm_bindgen]
+    pub fn focus(&self) {
+        self.with_app(|app, _| app.focus());
+    }
+
+    /// Calls `callback` with every event named `event_type` (e.g., `"LinesCleared"` or `"TopOut"`, `"*"` for all events),
+    /// the event is an object with the name as `type`, e.g., `{type: "LevelUp", level: 2}`. Returns the id to unsubscribe with.
+    #[wasm_bindgen]
+    pub fn subscribe(&self, event_type: &str, callback: js_sys::Function) -> u32 {
+        let listener = JsListener { event_type: event_type.to_string(), callback };
+        self.with_app(|app, _| app.subscribe(Box::new(listener)))
+    }
+
+    /// Returns whether there was a subscription with the id
+    #[wasm_bindgen]
+    pub fn unsubscribe(&self, id: u32) -> bool {
+        self.with_app(|app, _| app.unsubscribe(id))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WebHandle {
+    // Access the app, and show whatever changed
+    fn with_app<R>(&self, f: impl FnOnce(&mut TetrisApp, &egui::Context) -> R) -> R {
+        let mut runner = self.handle.lock();
+        let ctx = runner.egui_ctx().clone();
+        let result = f(runner.app_mut::<TetrisApp>(), &ctx);
+        ctx.request_repaint();
+        result
+    }
+}
+
+// Hands the events of the game to a JavaScript function
+#[cfg(target_arch = "wasm32")]
+struct JsListener {
+    event_type: String,
+    callback: js_sys::Function,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl GameObserver for JsListener {
+    fn notify(&mut self, event: &GameEvent, _game: &Tetris) {
+        if !matches!(self.event_type.as_str(), "*" | "") && self.event_type != event.name() {
+            return
+        }
+        let Ok(json) = serde_json::to_string(event) else { return };
+        let callback = self.callback.clone();
+
+        // The app is locked while the events are dispatched, so the callback runs after the frame
+        // and may use the handle again
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Ok(event) = js_sys::JSON::parse(&json) {
+                let _ = callback.call1(&JsValue::NULL, &event);
+            }
+        });
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn parse_options(query: &str) -> Result<Options, wasm_bindgen::JsValue> {
+    let (options, errors) = Options::from_query_str(query);
+    match errors.is_empty() {
+        true => Ok(options),
+        false => Err(JsValue::from_str(&errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))),
+    }
 }
 
 /// This is the entry-point for all the web-assembly.
 /// This is called once from the HTML.
 /// It loads the app, installs some callbacks, then returns.
 /// You can add more callbacks like this if you want to call in to your code.
+/// The game is set up from the page URL like the native app from its command line, e.g., `?mode=practice&seed=123&w=10&h=20&level=5`.
+#[cfg(target_arch = "wasm32")]
+pub async fn start_web(canvas_id: &str) -> Result<WebHandle, wasm_bindgen::JsValue> {
+    start(canvas_id, "", |cc| {
+        let query = &cc.integration_info.web_info.location.query_map;
+        let (options, errors) = Options::from_query(query.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+        for e in errors {
+            web_sys::console::warn_1(&JsValue::from_str(&format!("Ignoring URL parameter: {}", e)));
+        }
+        options
+    })
+    .await
+}
+
+/// Starts one of several games on a page, on the canvas with the id and set up by URL query parameters
+/// (e.g., `mode=practice&seed=3`). Each game stores its settings under the canvas id, and the keyboard
+/// goes to the game that was clicked last (or focused through its handle).
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub async fn start_instance(canvas_id: String, options: String) -> Result<WebHandle, wasm_bindgen::JsValue> {
+    let options = parse_options(&options)?;
+    start(&canvas_id, &canvas_id, move |_| options).await
+}
+
 #[cfg(target_arch = "wasm32")]
-pub async fn start_web(canvas_id: &str, width: i32, height: i32) -> Result<WebHandle, wasm_bindgen::JsValue> {
+async fn start(
+    canvas_id: &str,
+    name: &str,
+    options: impl FnOnce(&eframe::CreationContext<'_>) -> Options + 'static,
+) -> Result<WebHandle, wasm_bindgen::JsValue> {
     // Make sure panics are logged using `console.error`.
     console_error_panic_hook::set_once();
 
-    // Redirect tracing to console.log and friends:
-    tracing_wasm::set_as_global_default();
+    // Redirect tracing to console.log and friends, only once for all games on the page:
+    static TRACING: std::sync::Once = std::sync::Once::new();
+    TRACING.call_once(tracing_wasm::set_as_global_default);
 
     // Start web assembly thread
+    let name = name.to_string();
     let web_options = eframe::WebOptions::default();
     eframe::start_web(
         canvas_id,
         web_options,
-        Box::new(move |cc| Box::new(app::TetrisApp::new(cc, width, height))),
+        Box::new(move |cc| Box::new(app::TetrisApp::new_instance(cc, options(cc), &name))),
     )
     .await
     .map(|handle| WebHandle { handle })
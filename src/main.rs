@@ -1,12 +1,21 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-const GAME_WIDTH: i32 = 10;
-const GAME_HEIGHT: i32 = 20;
-
 #[cfg(not(target_arch = "wasm32"))]
 pub fn main() {
-    tetris::start_native("tetris_canvas", GAME_WIDTH, GAME_HEIGHT)
+    let options = match tetris::Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(tetris::OptionsError::Help) => {
+            println!("{}", tetris::USAGE);
+            return
+        },
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, tetris::USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    tetris::start_native("tetris_canvas", options)
         .expect("Couldn't start eframe");
 }
 
@@ -14,7 +23,7 @@ pub fn main() {
 #[cfg(target_arch = "wasm32")]
 pub fn main() {
     wasm_bindgen_futures::spawn_local(async {
-        tetris::start_web("tetris_canvas", GAME_WIDTH, GAME_HEIGHT)
+        tetris::start_web("tetris_canvas")
             .await
             .expect("Couldn't start eframe");
     });
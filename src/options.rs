@@ -0,0 +1,316 @@
+use std::fmt;
+
+use super::tetris::{Tetris, BoardSizeError};
+use super::replay::{Replay, ReplayError};
+use super::theme::Theme;
+use super::pieces::PieceSet;
+use super::rotation::RotationSystem;
+use super::enums::GameMode;
+
+pub const MAX_LEVEL: usize = 99;
+
+pub const USAGE: &str = "\
+Usage: tetris [OPTIONS]
+
+Options:
+  --width <CELLS>     Width of the board [default: 10]
+  --height <CELLS>    Height of the board [default: 20]
+  --seed <NUMBER>     Deal the same blocks in every game
+  --mode <MODE>       marathon, practice, sandbox or nes [default: marathon]
+  --level <LEVEL>     Level (gravity) the games start at, 1 to 99 (0 to 19 in nes) [default: 1]
+  --pieces <NAME>     Piece set by name, e.g. \"Pentomino\" [default: Tetromino]
+  --rotation <NAME>   Rotation system of the mode: SRS, SRS+, ARS or NES [default: SRS]
+  --theme <NAME>      Theme by name, e.g. \"High contrast\"
+  --fullscreen        Start in fullscreen
+  --windowed          Start in a window [default]
+  --replay <FILE>     Play back a replay, its size, seed, mode, level, pieces and rotation win over the options above
+  -h, --help          Print this help";
+
+/// Settings the app is started with
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub width: i32,
+    pub height: i32,
+    // Random games if not set
+    pub seed: Option<u64>,
+    pub mode: GameMode,
+    pub level: usize,
+    pub pieces: PieceSet,
+    // Rotation system for the mode, the one picked for the mode before if not set
+    pub rotation: Option<RotationSystem>,
+    pub theme: Option<Theme>,
+    pub fullscreen: bool,
+    pub replay: Option<Replay>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            height: 20,
+            seed: None,
+            mode: GameMode::Marathon,
+            level: 1,
+            pieces: PieceSet::default(),
+            rotation: None,
+            theme: None,
+            fullscreen: false,
+            replay: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses command line arguments (without the program name), `--name value` and `--name=value` are both accepted
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, OptionsError> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let name = match name.as_str() {
+                "-h" | "--help" => return Err(OptionsError::Help),
+                "--fullscreen" => { options.fullscreen = true; continue },
+                "--windowed" => { options.fullscreen = false; continue },
+                name => match name.strip_prefix("--") {
+                    Some(name) => name.to_string(),
+                    None => return Err(OptionsError::Unknown(name.to_string())),
+                },
+            };
+            let value = value.or_else(|| args.next()).ok_or_else(|| OptionsError::MissingValue(name.clone()))?;
+            options.set(&name, &value)?;
+        }
+        options.validate()
+    }
+
+    /// Parses URL query parameters, e.g., `?mode=practice&seed=123&w=10&h=20&level=5`.
+    /// Invalid parameters are skipped and reported, unknown ones (e.g. from analytics) are ignored.
+    pub fn from_query<'a>(params: impl IntoIterator<Item = (&'a str, &'a str)>) -> (Self, Vec<OptionsError>) {
+        let mut options = Self::default();
+        let mut errors = vec![];
+        for (name, value) in params {
+            match options.set(name, value) {
+                Ok(()) | Err(OptionsError::Unknown(_)) => (),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Err(e) = Tetris::check_size(options.width, options.height, &options.pieces) {
+            errors.push(OptionsError::BoardSize(e));
+            let Options { width, height, .. } = Self::default();
+            (options.width, options.height) = (width, height);
+        }
+        if let Err(e) = options.check_level() {
+            errors.push(e);
+            options.level = Self::default().level;
+        }
+        (options, errors)
+    }
+
+    /// Parses a query string like `mode=practice&seed=123` (with or without the leading `?`), see `from_query`
+    pub fn from_query_str(query: &str) -> (Self, Vec<OptionsError>) {
+        let params: Vec<(String, String)> = query.trim_start_matches('?')
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| match param.split_once('=') {
+                Some((name, value)) => (percent_decode(name), percent_decode(value)),
+                None => (percent_decode(param), String::new()),
+            })
+            .collect();
+        Self::from_query(params.iter().map(|(name, value)| (name.as_str(), value.as_str())))
+    }
+
+    /// The options as URL query parameters (without the `?`), the inverse of `from_query`
+    pub fn to_query(&self) -> String {
+        let mut params = vec![
+            format!("w={}", self.width),
+            format!("h={}", self.height),
+            format!("mode={}", format!("{:?}", self.mode).to_lowercase()),
+            format!("level={}", self.level),
+            format!("pieces={}", percent_encode(&self.pieces.name)),
+        ];
+        if let Some(rotation) = self.rotation {
+            params.push(format!("rotation={}", percent_encode(rotation.name())));
+        }
+        if let Some(seed) = self.seed {
+            params.push(format!("seed={}", seed));
+        }
+        if let Some(theme) = &self.theme {
+            params.push(format!("theme={}", percent_encode(&theme.name)));
+        }
+        params.join("&")
+    }
+
+    /// Sets one option by name, e.g., `set("width", "12")`
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), OptionsError> {
+        let invalid = || OptionsError::InvalidValue { name: name.to_string(), value: value.to_string() };
+        match name {
+            "width" | "w" => self.width = value.parse().map_err(|_| invalid())?,
+            "height" | "h" => self.height = value.parse().map_err(|_| invalid())?,
+            "seed" => self.seed = Some(value.parse().map_err(|_| invalid())?),
+            "mode" => self.mode = GameMode::from_name(value).ok_or_else(invalid)?,
+            "level" => self.level = value.parse().ok()
+                .filter(|level| (0..=MAX_LEVEL).contains(level))
+                .ok_or_else(invalid)?,
+            "pieces" => self.pieces = PieceSet::find(value).ok_or_else(|| OptionsError::UnknownPieces(value.to_string()))?,
+            "rotation" => self.rotation = Some(RotationSystem::from_name(value).ok_or_else(invalid)?),
+            "fullscreen" => self.fullscreen = value.parse().map_err(|_| invalid())?,
+            "theme" => self.theme = Some(Theme::available().into_iter()
+                .find(|theme| theme.name.eq_ignore_ascii_case(value))
+                .ok_or_else(|| OptionsError::UnknownTheme(value.to_string()))?),
+            #[cfg(not(target_arch = "wasm32"))]
+            "replay" => self.replay = Some(Replay::load(std::path::Path::new(value)).map_err(OptionsError::Replay)?),
+            name => return Err(OptionsError::Unknown(format!("--{}", name))),
+        }
+        Ok(())
+    }
+
+    /// Size of the board, the one of the replay if there is one
+    pub fn board_size(&self) -> (i32, i32) {
+        match &self.replay {
+            Some(replay) => (replay.width, replay.height),
+            None => (self.width, self.height),
+        }
+    }
+
+    /// Checks the options that depend on each other
+    pub fn validate(self) -> Result<Self, OptionsError> {
+        Tetris::check_size(self.width, self.height, &self.pieces).map_err(OptionsError::BoardSize)?;
+        self.check_level()?;
+        Ok(self)
+    }
+
+    // The level has to be one the mode can start at
+    fn check_level(&self) -> Result<(), OptionsError> {
+        match self.mode.ruleset().start_levels().contains(&self.level) {
+            true => Ok(()),
+            false => Err(OptionsError::LevelOutOfRange { level: self.level, mode: self.mode }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OptionsError {
+    // Not an error, the usage was asked for
+    Help,
+    Unknown(String),
+    MissingValue(String),
+    InvalidValue { name: String, value: String },
+    UnknownTheme(String),
+    UnknownPieces(String),
+    BoardSize(BoardSizeError),
+    LevelOutOfRange { level: usize, mode: GameMode },
+    Replay(ReplayError),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::Help => write!(f, "{}", USAGE),
+            OptionsError::Unknown(name) => write!(f, "unknown option {}", name),
+            OptionsError::MissingValue(name) => write!(f, "--{} needs a value", name),
+            OptionsError::InvalidValue { name, value } => write!(f, "invalid value '{}' for --{}", value, name),
+            OptionsError::UnknownTheme(name) => {
+                let names: Vec<String> = Theme::available().into_iter().map(|theme| theme.name).collect();
+                write!(f, "unknown theme '{}', the themes are: {}", name, names.join(", "))
+            },
+            OptionsError::UnknownPieces(name) => {
+                let names: Vec<String> = PieceSet::available().into_iter().map(|set| set.name).collect();
+                write!(f, "unknown piece set '{}', the sets are: {}", name, names.join(", "))
+            },
+            OptionsError::BoardSize(e) => write!(f, "{}", e),
+            OptionsError::LevelOutOfRange { level, mode } => {
+                let levels = mode.ruleset().start_levels();
+                write!(f, "level {} is not one of {} to {} of the {:?} mode", level, levels.start(), levels.end(), mode)
+            },
+            OptionsError::Replay(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// `+` is a space, `%XX` a byte, malformed escapes are kept as they are
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => { decoded.push(byte); i += 3 },
+            (b'+', None) => { decoded.push(b' '); i += 1 },
+            (byte, None) => { decoded.push(byte); i += 1 },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn rejects_levels_outside_the_mode() {
+        assert!(matches!(Options::from_args(args("--level 0")), Err(OptionsError::LevelOutOfRange { level: 0, .. })));
+        assert!(matches!(Options::from_args(args("--mode nes --level 20")), Err(OptionsError::LevelOutOfRange { level: 20, .. })));
+        assert_eq!(Options::from_args(args("--mode nes --level 0")).unwrap().level, 0);
+        assert_eq!(Options::from_args(args("--level 99")).unwrap().level, 99);
+
+        let (options, errors) = Options::from_query_str("mode=nes&level=25");
+        assert_eq!((options.level, errors.len()), (1, 1));
+    }
+    #[test]
+    fn parses_args_with_and_without_equals() {
+        let options = Options::from_args(args("--width=12 --height 24 --seed 5 --mode practice --fullscreen")).unwrap();
+        assert_eq!((options.width, options.height, options.seed, options.mode), (12, 24, Some(5), GameMode::Practice));
+        assert!(options.fullscreen);
+
+        assert!(matches!(Options::from_args(args("--help")), Err(OptionsError::Help)));
+        assert!(matches!(Options::from_args(args("--size 10")), Err(OptionsError::Unknown(name)) if name == "--size"));
+        assert!(matches!(Options::from_args(args("--seed")), Err(OptionsError::MissingValue(name)) if name == "seed"));
+        assert!(matches!(Options::from_args(args("--mode sprint")), Err(OptionsError::InvalidValue { .. })));
+        assert!(matches!(Options::from_args(args("--width 3")), Err(OptionsError::BoardSize(_))));
+    }
+    #[test]
+    fn query_round_trips() {
+        let mut options = Options::from_args(args("--mode practice --level 5 --seed 123 --rotation SRS+")).unwrap();
+        options.pieces = PieceSet::find("Tromino+Tetromino").unwrap();
+        options.theme = Some(Theme::high_contrast());
+        let (parsed, errors) = Options::from_query_str(&format!("?{}", options.to_query()));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(parsed.to_query(), options.to_query());
+        assert_eq!(parsed.theme, options.theme);
+        assert_eq!(parsed.rotation, options.rotation);
+    }
+
+    #[test]
+    fn skips_invalid_query_params() {
+        let (options, errors) = Options::from_query_str("w=3&h=20&seed=abc&utm_source=mail&mode=Sandbox&theme=High+contrast");
+        assert_eq!((options.width, options.height, options.seed), (10, 20, None));
+        assert_eq!(options.mode, GameMode::Sandbox);
+        assert_eq!(options.theme, Some(Theme::high_contrast()));
+        assert!(matches!(errors[..], [OptionsError::InvalidValue { .. }, OptionsError::BoardSize(_)]), "{:?}", errors);
+    }
+}
+
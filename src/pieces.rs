@@ -0,0 +1,252 @@
+use std::fmt;
+
+use egui::Color32;
+use serde::{Serialize, Deserialize};
+
+use super::util::Coord;
+use super::enums::BlockID;
+
+// Piece sets that are built into the binary, so they also work on the web
+const PIECE_SETS: [&str; 3] = [
+    include_str!("../assets/pieces/tetromino.json"),
+    include_str!("../assets/pieces/tromino-tetromino.json"),
+    include_str!("../assets/pieces/pentomino.json"),
+];
+
+// Folder searched for additional piece sets on native
+#[cfg(not(target_arch = "wasm32"))]
+const PIECE_DIR: &str = "assets/pieces";
+
+// `BlockID::G` is taken by garbage, which observations of the environment count as 255
+const MAX_PIECES: usize = u8::MAX as usize - 1;
+
+/// Shape and color of a piece, in its spawn orientation with `y` pointing down
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Piece {
+    pub name: String,
+    pub cells: Vec<Coord>,
+    // The cell the piece rotates around
+    pub center: Coord,
+    // Pieces that look the same in every orientation (like O) stay where they are instead
+    #[serde(default = "rotates")]
+    pub rotates: bool,
+    // Moves the piece away from where it spawns, which is centered at the top of the board
+    #[serde(default)]
+    pub spawn: Coord,
+    pub color: Color32,
+}
+
+impl Piece {
+    /// The tetromino the piece is, going by its name, e.g., a four cell piece named `T` is T-spun like one
+    pub fn tetromino(&self) -> Option<BlockID> {
+        let mut letters = self.name.chars();
+        match (letters.next(), letters.next()) {
+            (Some(c), None) if self.cells.len() == 4 => BlockID::from_char(c),
+            _ => None,
+        }
+    }
+
+    /// Letter drawn on the cells of the piece
+    pub fn glyph(&self) -> Option<char> {
+        self.name.chars().next()
+    }
+}
+
+/// The pieces a game is dealt, loadable from JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceSet {
+    pub name: String,
+    // The `BlockID` of a piece is its position
+    pub pieces: Vec<Piece>,
+}
+
+impl PieceSet {
+    /// The seven tetrominoes, in `BlockID::ALL` order
+    pub fn tetrominoes() -> Self {
+        Self::from_json(PIECE_SETS[0]).expect("built-in piece sets are valid")
+    }
+
+    /// The built-in piece sets
+    pub fn presets() -> Vec<PieceSet> {
+        PIECE_SETS.iter()
+            .map(|json| Self::from_json(json).expect("built-in piece sets are valid"))
+            .collect()
+    }
+
+    /// The built-in piece sets followed by the ones found in `assets/pieces/` (native only)
+    pub fn available() -> Vec<PieceSet> {
+        let mut sets = Self::presets();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(entries) = std::fs::read_dir(PIECE_DIR) {
+            let mut paths: Vec<_> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect();
+            paths.sort();
+            for set in paths.iter()
+                .filter_map(|path| std::fs::read_to_string(path).ok())
+                .filter_map(|json| Self::from_json(&json).ok()) {
+                // The built-in sets are also found in the folder when started from the repository
+                if !sets.contains(&set) {
+                    sets.push(set);
+                }
+            }
+        }
+        sets
+    }
+
+    /// The available set with the name (case insensitive)
+    pub fn find(name: &str) -> Option<PieceSet> {
+        Self::available().into_iter().find(|set| set.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Parses and checks a piece set
+    pub fn from_json(json: &str) -> Result<Self, PieceSetError> {
+        let set: PieceSet = serde_json::from_str(json).map_err(|e| PieceSetError::Parse(e.to_string()))?;
+        if set.pieces.is_empty() {
+            return Err(PieceSetError::NoPieces)
+        }
+        if set.pieces.len() > MAX_PIECES {
+            return Err(PieceSetError::TooManyPieces(set.pieces.len()))
+        }
+        if set.pieces.iter().any(|piece| piece.name.is_empty()) {
+            return Err(PieceSetError::NoName)
+        }
+        if let Some(piece) = set.pieces.iter().find(|piece| piece.cells.is_empty()) {
+            return Err(PieceSetError::NoCells(piece.name.clone()))
+        }
+        Ok(set)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    pub fn get(&self, id: BlockID) -> Option<&Piece> {
+        self.pieces.get(id.index())
+    }
+
+    /// Columns taken by the widest piece, a board has to be at least that wide
+    pub fn max_width(&self) -> i32 {
+        self.pieces.iter()
+            .map(|piece| {
+                let xs = piece.cells.iter().map(|c| c.0);
+                xs.clone().max().unwrap_or(0) - xs.min().unwrap_or(0) + 1
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Ids of all pieces in the set
+    pub fn ids(&self) -> impl Iterator<Item=BlockID> {
+        (0..self.pieces.len()).map(|i| BlockID(i as u8))
+    }
+
+    /// The tetromino the piece `id` is, see `Piece::tetromino`
+    pub fn tetromino(&self, id: BlockID) -> Option<BlockID> {
+        self.get(id).and_then(Piece::tetromino)
+    }
+
+    /// Reads pieces by name, longest name first (so `I3` is not read as `I` and `3`), other characters are skipped
+    pub fn parse_queue(&self, text: &str) -> Vec<BlockID> {
+        let mut queue = vec![];
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let found = self.ids()
+                .zip(&self.pieces)
+                .filter(|(_, piece)| !piece.name.is_empty()
+                    && rest.get(..piece.name.len()).is_some_and(|start| start.eq_ignore_ascii_case(&piece.name)))
+                .max_by_key(|(_, piece)| piece.name.len());
+            match found {
+                Some((id, piece)) => {
+                    queue.push(id);
+                    rest = &rest[piece.name.len()..];
+                },
+                None => rest = &rest[c.len_utf8()..],
+            }
+        }
+        queue
+    }
+}
+
+impl Default for PieceSet {
+    fn default() -> Self {
+        Self::tetrominoes()
+    }
+}
+
+#[derive(Debug)]
+pub enum PieceSetError {
+    Parse(String),
+    NoPieces,
+    TooManyPieces(usize),
+    NoName,
+    NoCells(String),
+}
+
+impl fmt::Display for PieceSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PieceSetError::Parse(e) => write!(f, "Invalid piece set: {}", e),
+            PieceSetError::NoPieces => write!(f, "A piece set needs at least one piece"),
+            PieceSetError::TooManyPieces(n) => write!(f, "A piece set has at most {} pieces, not {}", MAX_PIECES, n),
+            PieceSetError::NoName => write!(f, "Every piece needs a name"),
+            PieceSetError::NoCells(name) => write!(f, "Piece {} has no cells", name),
+        }
+    }
+}
+
+impl std::error::Error for PieceSetError {}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+fn rotates() -> bool {
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid_and_the_first_is_the_tetrominoes() {
+        let presets = PieceSet::presets();
+        assert_eq!(presets[0].ids().map(|id| presets[0].tetromino(id)).collect::<Vec<_>>(), BlockID::ALL.map(Some));
+        assert_eq!(presets.iter().map(PieceSet::max_width).collect::<Vec<_>>(), [4, 4, 5]);
+        for set in presets {
+            assert_eq!(PieceSet::from_json(&set.to_json()).unwrap(), set);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sets() {
+        let piece = r#"{ "name": "X", "cells": [[0, 0]], "center": [0, 0], "color": [0, 0, 0, 255] }"#;
+        let set = |pieces: &str| PieceSet::from_json(&format!(r#"{{ "name": "Set", "pieces": [{}] }}"#, pieces));
+        assert!(set(piece).is_ok());
+        assert!(matches!(set(""), Err(PieceSetError::NoPieces)));
+        assert!(matches!(set(&piece.replace("\"X\"", "\"\"")), Err(PieceSetError::NoName)));
+        assert!(matches!(set(&piece.replace("[[0, 0]]", "[]")), Err(PieceSetError::NoCells(name)) if name == "X"));
+        assert!(matches!(set(&vec![piece; MAX_PIECES + 1].join(",")), Err(PieceSetError::TooManyPieces(_))));
+        assert!(matches!(set("{}"), Err(PieceSetError::Parse(_))));
+    }
+
+    #[test]
+    fn queues_are_read_longest_name_first() {
+        let set = PieceSet::find("Tromino+Tetromino").unwrap();
+        let id = |name: &str| set.ids().find(|&id| set.get(id).unwrap().name == name).unwrap();
+        assert_eq!(set.parse_queue("I3 i, L3T"), [id("I3"), id("I"), id("L3"), id("T")]);
+        // Only the tetrominoes are T-spun and take the theme colors
+        assert_eq!(set.tetromino(id("I3")), None);
+        assert_eq!(set.get(id("I3")).unwrap().glyph(), Some('I'));
+    }
+}
@@ -0,0 +1,93 @@
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyDict;
+
+use super::env::{Env, EnvConfig, ActionSpace, Observation, Info};
+use super::bot::Placement;
+use super::tetris::Tetris;
+use super::pieces::PieceSet;
+use super::enums::BlockID;
+
+/// Python wrapper of `Env`, observations and infos are handed out as dicts
+#[pyclass(name = "TetrisEnv")]
+pub struct PyEnv {
+    env: Env,
+}
+
+#[pymethods]
+impl PyEnv {
+    #[new]
+    #[pyo3(signature = (width=10, height=20, action_space="placement", gravity_steps=10, queue_len=5))]
+    fn new(width: i32, height: i32, action_space: &str, gravity_steps: usize, queue_len: usize) -> PyResult<Self> {
+        Tetris::check_size(width, height, &PieceSet::tetrominoes())
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let action_space = match action_space {
+            "keypress" => ActionSpace::Keypress,
+            "placement" => ActionSpace::Placement,
+            other => return Err(PyValueError::new_err(format!("unknown action space '{}'", other))),
+        };
+        let config = EnvConfig { width, height, action_space, gravity_steps, queue_len };
+        Ok(Self { env: Env::new(config) })
+    }
+
+    #[pyo3(signature = (seed=0))]
+    fn reset(&mut self, py: Python<'_>, seed: u64) -> PyResult<PyObject> {
+        let observation = self.env.reset(seed);
+        observation_to_dict(py, &observation, self.env.game().pieces())
+    }
+
+    fn step(&mut self, py: Python<'_>, action: usize) -> PyResult<(PyObject, f32, bool, PyObject)> {
+        let (observation, reward, done, info) = self.env.step(action)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok((observation_to_dict(py, &observation, self.env.game().pieces())?, reward, done, info_to_dict(py, &info)?))
+    }
+
+    /// Number of valid actions in the next step
+    fn num_actions(&self) -> usize {
+        self.env.num_actions()
+    }
+
+    /// The reachable placements of the current block, indexed by the placement actions
+    fn placements(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        self.env.placements().iter()
+            .zip(self.env.placement_cells())
+            .map(|(placement, cells)| placement_to_dict(py, placement, cells))
+            .collect()
+    }
+}
+
+// Pieces are handed out by name
+fn observation_to_dict(py: Python<'_>, observation: &Observation, pieces: &PieceSet) -> PyResult<PyObject> {
+    let name = |id: BlockID| pieces.get(id).map_or(String::new(), |piece| piece.name.clone());
+    let dict = PyDict::new(py);
+    dict.set_item("board", &observation.board)?;
+    dict.set_item("current", &observation.current)?;
+    dict.set_item("current_id", name(observation.current_id))?;
+    dict.set_item("queue", observation.queue.iter().map(|&id| name(id)).collect::<Vec<_>>())?;
+    dict.set_item("hold", observation.hold.map(name))?;
+    Ok(dict.into())
+}
+
+// Cells are handed out as (x, y) pairs on the observed board
+fn placement_to_dict(py: Python<'_>, placement: &Placement, cells: Vec<(i32, i32)>) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("cells", cells)?;
+    dict.set_item("score", placement.score)?;
+    Ok(dict.into())
+}
+
+fn info_to_dict(py: Python<'_>, info: &Info) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("lines_cleared", info.lines_cleared)?;
+    dict.set_item("score", info.score)?;
+    dict.set_item("total_lines", info.total_lines)?;
+    dict.set_item("pieces", info.pieces)?;
+    dict.set_item("steps", info.steps)?;
+    dict.set_item("num_actions", info.num_actions)?;
+    Ok(dict.into())
+}
+
+#[pymodule]
+fn tetris(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEnv>()
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use super::tetris::{Tetris, Delays};
+use super::pieces::PieceSet;
+use super::rotation::RotationSystem;
+use super::enums::{GameCmd, GameMode};
+
+/// Bump whenever the replay layout changes, replays of other versions are rejected
+pub const REPLAY_VERSION: u32 = 1;
+
+/// Everything that changes a game, replaying them on a game with the same settings gives the same game
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReplayAction {
+    // A command of the player (or bot)
+    Cmd(GameCmd),
+    // Gravity pulled the block down a row
+    Tick,
+    // Time (in ms) passed during a delay
+    Update(i64),
+    // The delays were changed during the game
+    Delays(Delays),
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+struct Step {
+    // Game time at which the action happened
+    time_ms: i64,
+    action: ReplayAction,
+}
+
+/// The settings of a game and every action taken in it, stored as JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    version: u32,
+    pub width: i32,
+    pub height: i32,
+    pub seed: u64,
+    pub mode: GameMode,
+    pub start_level: usize,
+    pub delays: Delays,
+    pub pieces: PieceSet,
+    pub rotation: RotationSystem,
+    steps: Vec<Step>,
+}
+
+impl Replay {
+    /// An empty replay of a game started from `game`, which has to be a fresh game dealt from `seed`
+    pub fn new(game: &Tetris, seed: u64, mode: GameMode) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            width: game.width(),
+            height: game.height(),
+            seed,
+            mode,
+            start_level: game.start_level(),
+            delays: game.delays(),
+            pieces: game.pieces().clone(),
+            rotation: game.rotation(),
+            steps: vec![],
+        }
+    }
+
+    /// The game as it was before the first action
+    pub fn new_game(&self) -> Tetris {
+        let mut game = Tetris::with_pieces(self.width, self.height, self.seed, self.pieces.clone());
+        game.set_rules(self.mode.ruleset());
+        game.set_rotation(self.rotation);
+        game.set_start_level(self.start_level);
+        game.set_delays(self.delays);
+        game
+    }
+
+    pub fn record(&mut self, time_ms: i64, action: ReplayAction) {
+        self.steps.push(Step { time_ms, action });
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Game time of the last action
+    pub fn duration_ms(&self) -> i64 {
+        self.steps.last().map_or(0, |step| step.time_ms)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ReplayError> {
+        let replay: Replay = serde_json::from_str(json).map_err(|e| ReplayError::Parse(e.to_string()))?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::Version(replay.version))
+        }
+        Tetris::check_size(replay.width, replay.height, &replay.pieces).map_err(|e| ReplayError::Parse(e.to_string()))?;
+        Ok(replay)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &std::path::Path) -> Result<Self, ReplayError> {
+        let json = std::fs::read_to_string(path).map_err(|e| ReplayError::Io(format!("{}: {}", path.display(), e)))?;
+        Self::from_json(&json)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &std::path::Path) -> Result<(), ReplayError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| ReplayError::Io(format!("{}: {}", dir.display(), e)))?;
+        }
+        std::fs::write(path, self.to_json()).map_err(|e| ReplayError::Io(format!("{}: {}", path.display(), e)))
+    }
+}
+
+/// Hands out the actions of a replay as the game time passes
+#[derive(Debug, Clone)]
+pub struct Playback {
+    replay: Replay,
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next: 0 }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// The actions that happened up to `time_ms` and were not handed out yet
+    pub fn advance(&mut self, time_ms: i64) -> Vec<ReplayAction> {
+        let steps = &self.replay.steps[self.next..];
+        let due = steps.iter().take_while(|step| step.time_ms <= time_ms).count();
+        self.next += due;
+        steps[..due].iter().map(|step| step.action).collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.replay.steps.len()
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(String),
+    Parse(String),
+    Version(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "Couldn't access replay {}", e),
+            ReplayError::Parse(e) => write!(f, "Invalid replay: {}", e),
+            ReplayError::Version(version) =>
+                write!(f, "Replay version {} is not supported, expected version {}", version, REPLAY_VERSION),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{ShiftCmd, RotateCmd};
+
+    // Does what the app does with an action during playback
+    fn apply(game: &mut Tetris, action: ReplayAction) {
+        match action {
+            ReplayAction::Cmd(cmd) => { game.execute(&cmd); },
+            ReplayAction::Tick => { game.tick(); },
+            ReplayAction::Update(ms) => { game.update(ms); },
+            ReplayAction::Delays(delays) => game.set_delays(delays),
+        }
+    }
+
+    #[test]
+    fn playback_reproduces_the_game() {
+        // Set up like a fresh game of the app, which deals its first block for the rules of the mode
+        let mut game = Tetris::new(10, 20, 7);
+        game.set_rules(GameMode::Marathon.ruleset());
+        let mut replay = Replay::new(&game, 7, GameMode::Marathon);
+        let mut time_ms = 0;
+        for i in 0..20 {
+            let mut actions = vec![
+                ReplayAction::Tick,
+                ReplayAction::Cmd(GameCmd::Rotate(if i % 2 == 0 { RotateCmd::Right } else { RotateCmd::Left })),
+            ];
+            let shift = if i % 3 == 0 { ShiftCmd::Left } else { ShiftCmd::Right };
+            actions.extend(std::iter::repeat_n(ReplayAction::Cmd(GameCmd::Shift(shift)), i % 5));
+            if i % 4 == 1 {
+                actions.push(ReplayAction::Cmd(GameCmd::Hold));
+            }
+            actions.push(ReplayAction::Cmd(GameCmd::HardDrop));
+            actions.push(ReplayAction::Update(50));
+            if i == 10 {
+                actions.push(ReplayAction::Delays(Delays { entry_ms: 30, line_clear_ms: 20 }));
+            }
+            for action in actions {
+                time_ms += 25;
+                apply(&mut game, action);
+                replay.record(time_ms, action);
+            }
+        }
+        assert_eq!(replay.duration_ms(), time_ms);
+
+        let mut playback = Playback::new(Replay::from_json(&replay.to_json()).unwrap());
+        let mut replayed = playback.replay().new_game();
+        let mut now = 0;
+        while !playback.is_finished() {
+            now += 40;
+            playback.advance(now).into_iter().for_each(|action| apply(&mut replayed, action));
+        }
+        assert_eq!(replayed.playfield(), game.playfield());
+        assert_eq!(replayed.score(), game.score());
+        let cells = |game: &Tetris| {
+            let mut cells: Vec<_> = game.current_block().config().map(|c| (c.0, c.1)).collect();
+            cells.sort();
+            cells
+        };
+        assert_eq!(cells(&replayed), cells(&game));
+    }
+
+    #[test]
+    fn rejects_replays_of_other_versions() {
+        let mut replay = Replay::new(&Tetris::new(10, 20, 1), 1, GameMode::Marathon);
+        replay.version = REPLAY_VERSION + 1;
+        assert!(matches!(Replay::from_json(&replay.to_json()), Err(ReplayError::Version(v)) if v == REPLAY_VERSION + 1));
+    }
+}
@@ -0,0 +1,329 @@
+use serde::{Serialize, Deserialize};
+
+use super::block::Block;
+use super::pieces::{Piece, PieceSet};
+use super::util::Coord;
+use super::enums::{BlockID, RotateCmd};
+
+// Tetrominoes in their four orientations (spawn, right, reverse, left), drawn with `y` pointing down.
+// The middle of the box (column 1, row 1) is the center of the block, and the spawn orientation is
+// lined up with the bottom left corner of the piece in the set.
+type Shapes = [&'static str; 4];
+
+const SRS_SHAPES: [Shapes; 7] = [
+    ["..../####/..../....", "..#./..#./..#./..#.", "..../..../####/....", ".#../.#../.#../.#.."],
+    ["#../###/...", ".##/.#./.#.", ".../###/..#", ".#./.#./##."],
+    ["..#/###/...", ".#./.#./.##", ".../###/#..", "##./.#./.#."],
+    [".##/.##/...", ".##/.##/...", ".##/.##/...", ".##/.##/..."],
+    [".##/##./...", ".#./.##/..#", ".../.##/##.", "#../##./.#."],
+    [".#./###/...", ".#./.##/.#.", ".../###/.#.", ".#./##./.#."],
+    ["##./.##/...", "..#/.##/.#.", ".../##./.##", ".#./##./#.."],
+];
+
+// Flat side up, and bottom aligned, I, S and Z only have two orientations
+const ARS_SHAPES: [Shapes; 7] = [
+    ["..../####/..../....", "..#./..#./..#./..#.", "..../####/..../....", "..#./..#./..#./..#."],
+    [".../###/..#", ".#./.#./##.", ".../#../###", ".##/.#./.#."],
+    [".../###/#..", "##./.#./.#.", ".../..#/###", ".#./.#./.##"],
+    [".../.##/.##", ".../.##/.##", ".../.##/.##", ".../.##/.##"],
+    [".../.##/##.", "#../##./.#.", ".../.##/##.", "#../##./.#."],
+    [".../###/.#.", ".#./##./.#.", ".../.#./###", ".#./.##/.#."],
+    [".../##./.##", "..#/.##/.#.", ".../##./.##", "..#/.##/.#."],
+];
+
+// Flat side up, and turning around the middle cell, I, S and Z only have two orientations which lean right
+const NES_SHAPES: [Shapes; 7] = [
+    ["..../..../####/....", "..#./..#./..#./..#.", "..../..../####/....", "..#./..#./..#./..#."],
+    [".../###/..#", ".#./.#./##.", "#../###/...", ".##/.#./.#."],
+    [".../###/#..", "##./.#./.#.", "..#/###/...", ".#./.#./.##"],
+    [".../.##/.##", ".../.##/.##", ".../.##/.##", ".../.##/.##"],
+    [".../.##/##.", ".#./.##/..#", ".../.##/##.", ".#./.##/..#"],
+    [".../###/.#.", ".#./##./.#.", ".#./###/...", ".#./.##/.#."],
+    [".../##./.##", "..#/.##/.#.", ".../##./.##", "..#/.##/.#."],
+];
+
+// Kicks as in the guideline, with `y` pointing up, indexed by the orientation turned from
+const SRS_KICKS_RIGHT: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+const SRS_KICKS_LEFT: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+const SRS_I_KICKS_RIGHT: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
+const SRS_I_KICKS_LEFT: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+];
+
+// SRS+ kicks the I the same way in both directions, and has kicks for half turns
+const SRS_PLUS_I_KICKS_RIGHT: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (1, 0), (-2, 0), (-2, -1), (1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+];
+const SRS_PLUS_I_KICKS_LEFT: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (2, -1), (-1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
+const SRS_PLUS_KICKS_HALF: [[(i32, i32); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
+
+// A blocked turn is tried one column to the right, then to the left
+const ARS_KICKS: [(i32, i32); 3] = [(0, 0), (1, 0), (-1, 0)];
+
+/// Orientation of a block, counted in clockwise quarter turns from its spawn orientation
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    Spawn,
+    Right,
+    Reverse,
+    Left,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 4] = [Orientation::Spawn, Orientation::Right, Orientation::Reverse, Orientation::Left];
+
+    /// Clockwise quarter turns from the spawn orientation
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// The orientation after turning by `cmd`
+    pub fn turned(&self, cmd: &RotateCmd) -> Orientation {
+        let quarter_turns = match cmd {
+            RotateCmd::Right => 1,
+            RotateCmd::Left => 3,
+            RotateCmd::Half => 2,
+        };
+        Self::ALL[(self.index() + quarter_turns) % 4]
+    }
+}
+
+/// How blocks turn: their spawn orientations, the shapes they turn through and the offsets (kicks)
+/// tried one after another when a turn is blocked
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RotationSystem {
+    // Super Rotation System of the guideline
+    #[default]
+    Srs,
+    // SRS with symmetric I kicks and kicks for half turns
+    SrsPlus,
+    // Arika Rotation System (TGM), flat side up and kicking one column to either side
+    Ars,
+    // Classic NES rotation, flat side up, right-handed and without kicks
+    Nes,
+}
+
+impl RotationSystem {
+    pub const ALL: [RotationSystem; 4] = [RotationSystem::Srs, RotationSystem::SrsPlus, RotationSystem::Ars, RotationSystem::Nes];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RotationSystem::Srs => "SRS",
+            RotationSystem::SrsPlus => "SRS+",
+            RotationSystem::Ars => "ARS",
+            RotationSystem::Nes => "NES",
+        }
+    }
+
+    /// The system with the name (case insensitive), `+` may be spelled `plus`
+    pub fn from_name(name: &str) -> Option<RotationSystem> {
+        let name = name.to_ascii_lowercase().replace("plus", "+");
+        Self::ALL.into_iter().find(|system| system.name().eq_ignore_ascii_case(&name))
+    }
+
+    /// A piece in its spawn orientation, not yet placed on the board. Ids outside the set (garbage) are a single cell
+    pub fn spawn(&self, pieces: &PieceSet, id: BlockID) -> Block {
+        self.oriented(pieces, id, Orientation::Spawn)
+    }
+
+    /// A piece in the orientation, not yet placed on the board
+    pub fn oriented(&self, pieces: &PieceSet, id: BlockID, orientation: Orientation) -> Block {
+        match pieces.get(id) {
+            Some(piece) => {
+                let (cells, center) = self.shape(piece, orientation);
+                Block::new(id, cells, center, orientation)
+            },
+            None => Block::cell(id, Coord(0, 0)),
+        }
+    }
+
+    /// The block turned by `cmd` around its center, before any kick
+    pub fn turned(&self, pieces: &PieceSet, block: &Block, cmd: &RotateCmd) -> Block {
+        let turned = self.oriented(pieces, *block.id(), block.orientation().turned(cmd));
+        let Coord(x, y) = block.center();
+        let Coord(tx, ty) = turned.center();
+        &turned + Coord(x - tx, y - ty)
+    }
+
+    /// Turns the block by `cmd`, kicked by the first offset at which it `fits`. Returns the turned block
+    /// and the kick, `None` if the block doesn't fit anywhere
+    pub fn rotate(&self, pieces: &PieceSet, block: &Block, cmd: &RotateCmd, fits: impl Fn(&Block) -> bool) -> Option<(Block, Coord)> {
+        let turned = self.turned(pieces, block, cmd);
+        self.kicks(pieces.tetromino(*block.id()), block.orientation(), turned.orientation())
+            .into_iter()
+            .map(|kick| (&turned + kick, kick))
+            .find(|(block, _)| fits(block))
+    }
+
+    /// Offsets (`y` pointing down) tried in order when the tetromino (`None` for other pieces) turns from `from` to `to`
+    pub fn kicks(&self, tetromino: Option<BlockID>, from: Orientation, to: Orientation) -> Vec<Coord> {
+        let quarter_turns = (to.index() + 4 - from.index()) % 4;
+        let table: &[(i32, i32)] = match (self, tetromino, quarter_turns) {
+            (_, _, 0) | (RotationSystem::Nes, _, _) | (_, Some(BlockID::O), _) => &[(0, 0)],
+            // Only SRS+ has kicks for half turns, the other systems turn in place
+            (RotationSystem::SrsPlus, _, 2) => &SRS_PLUS_KICKS_HALF[from.index()],
+            (_, _, 2) => &[(0, 0)],
+            (RotationSystem::Ars, Some(BlockID::I), _) => &[(0, 0)],
+            (RotationSystem::Ars, _, _) | (_, None, _) => &ARS_KICKS,
+            (RotationSystem::SrsPlus, Some(BlockID::I), 1) => &SRS_PLUS_I_KICKS_RIGHT[from.index()],
+            (RotationSystem::SrsPlus, Some(BlockID::I), _) => &SRS_PLUS_I_KICKS_LEFT[from.index()],
+            (_, Some(BlockID::I), 1) => &SRS_I_KICKS_RIGHT[from.index()],
+            (_, Some(BlockID::I), _) => &SRS_I_KICKS_LEFT[from.index()],
+            (_, _, 1) => &SRS_KICKS_RIGHT[from.index()],
+            _ => &SRS_KICKS_LEFT[from.index()],
+        };
+        table.iter().map(|&(x, y)| Coord(x, -y)).collect()
+    }
+
+    // Cells and center of the piece in the orientation, in the coordinates of the piece set.
+    // Tetrominoes are drawn by the system, other pieces turn around their center
+    fn shape(&self, piece: &Piece, orientation: Orientation) -> (Vec<Coord>, Coord) {
+        let Some(tetromino) = piece.tetromino() else {
+            let cells = piece.cells.iter()
+                .map(|&cell| (0..orientation.index()).filter(|_| piece.rotates).fold(cell, |c, _| {
+                    let Coord(cx, cy) = piece.center;
+                    Coord(cx - (c.1 - cy), cy + (c.0 - cx))
+                }))
+                .collect();
+            return (cells, piece.center)
+        };
+
+        let shapes = match self {
+            RotationSystem::Srs | RotationSystem::SrsPlus => &SRS_SHAPES,
+            RotationSystem::Ars => &ARS_SHAPES,
+            RotationSystem::Nes => &NES_SHAPES,
+        };
+        let spawn = parse_shape(shapes[tetromino.index()][0]);
+        let cells = parse_shape(shapes[tetromino.index()][orientation.index()]);
+
+        // Line up the bottom left corner of the spawn orientation with the one of the piece
+        let left = |cells: &[Coord]| cells.iter().map(|c| c.0).min().unwrap_or(0);
+        let bottom = |cells: &[Coord]| cells.iter().map(|c| c.1).max().unwrap_or(0);
+        let offset = Coord(left(&piece.cells) - left(&spawn), bottom(&piece.cells) - bottom(&spawn));
+        (cells.into_iter().map(|c| c + offset).collect(), Coord(1, 1) + offset)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+// Cells marked `#`, rows are separated by `/`
+fn parse_shape(shape: &str) -> Vec<Coord> {
+    shape.split('/')
+        .enumerate()
+        .flat_map(|(y, row)| row.chars()
+            .enumerate()
+            .filter(|&(_, c)| c == '#')
+            .map(move |(x, _)| Coord(x as i32, y as i32)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::Tetris;
+    use crate::enums::Ruleset;
+
+    #[test]
+    fn half_turns_in_every_system_but_not_under_nes_rules() {
+        for rotation in RotationSystem::ALL {
+            for rules in [Ruleset::Modern, Ruleset::Nes] {
+                let mut game = Tetris::new(10, 20, 1);
+                game.set_rules(rules);
+                game.set_rotation(rotation);
+                let turned = game.rotated(game.current_block(), &RotateCmd::Half);
+                assert_eq!(turned.is_some(), rules.has_half_turn(), "{} under {:?}", rotation.name(), rules);
+            }
+        }
+    }
+
+    #[test]
+    fn srs_plus_half_turns_kick_off_the_floor() {
+        let kicks = RotationSystem::SrsPlus.kicks(Some(BlockID::T), Orientation::Spawn, Orientation::Reverse);
+        assert_eq!(kicks, [Coord(0, 0), Coord(0, -1), Coord(1, -1), Coord(-1, -1), Coord(1, 0), Coord(-1, 0)]);
+        assert_eq!(RotationSystem::Srs.kicks(Some(BlockID::T), Orientation::Spawn, Orientation::Reverse), [Coord(0, 0)]);
+
+        // A T resting on the floor with its flat side down turns over by moving up a row
+        let mut game = Tetris::new(10, 20, 1);
+        game.set_rotation(RotationSystem::SrsPlus);
+        let mut block = &RotationSystem::SrsPlus.oriented(game.pieces(), BlockID::T, Orientation::Spawn) + Coord(4, 0);
+        while game.is_feasible(&(&block + Coord(0, 1))) {
+            block = &block + Coord(0, 1);
+        }
+        let (turned, kick) = game.rotated(&block, &RotateCmd::Half).unwrap();
+        assert_eq!(turned.orientation(), Orientation::Reverse);
+        assert_eq!(kick, Coord(0, -1));
+    }
+    #[test]
+    fn systems_by_name() {
+        for system in RotationSystem::ALL {
+            assert_eq!(RotationSystem::from_name(&system.name().to_lowercase()), Some(system));
+        }
+        assert_eq!(RotationSystem::from_name("SRSplus"), Some(RotationSystem::SrsPlus));
+        assert_eq!(RotationSystem::from_name("TGM"), None);
+    }
+
+    #[test]
+    fn kicks_of_each_system() {
+        let (spawn, right) = (Orientation::Spawn, Orientation::Right);
+        assert_eq!(RotationSystem::Ars.kicks(Some(BlockID::T), spawn, right), [Coord(0, 0), Coord(1, 0), Coord(-1, 0)]);
+        assert_eq!(RotationSystem::Ars.kicks(Some(BlockID::I), spawn, right), [Coord(0, 0)]);
+        assert_eq!(RotationSystem::Nes.kicks(Some(BlockID::T), spawn, right), [Coord(0, 0)]);
+        // The guideline kicks have `y` pointing up, the board has it pointing down
+        assert_eq!(RotationSystem::Srs.kicks(Some(BlockID::I), spawn, right)[3], Coord(-2, 1));
+        assert_eq!(RotationSystem::Srs.kicks(Some(BlockID::T), spawn, right)[2], Coord(-1, -1));
+        // Pieces other than tetrominoes kick like ARS in every system
+        assert_eq!(RotationSystem::Srs.kicks(None, spawn, right), [Coord(0, 0), Coord(1, 0), Coord(-1, 0)]);
+    }
+
+    #[test]
+    fn four_quarter_turns_are_a_full_turn() {
+        let pieces = PieceSet::find("Tromino+Tetromino").unwrap();
+        let cells = |block: &Block| {
+            let mut cells: Vec<_> = block.config().map(|c| (c.0, c.1)).collect();
+            cells.sort();
+            cells
+        };
+        for system in RotationSystem::ALL {
+            for id in pieces.ids() {
+                let block = system.spawn(&pieces, id);
+                let turned = (0..4).fold(block.clone(), |turned, _| system.turned(&pieces, &turned, &RotateCmd::Right));
+                assert_eq!(cells(&turned), cells(&block), "{} {}", system.name(), pieces.get(id).unwrap().name);
+                assert_eq!(turned.orientation(), Orientation::Spawn);
+            }
+        }
+    }
+}
+
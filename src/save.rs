@@ -0,0 +1,64 @@
+use serde::{Serialize, Deserialize};
+
+use super::tetris::Tetris;
+use super::timer::TickTimer;
+use super::enums::GameMode;
+
+/// Bump whenever the saved layout changes, saves of other versions are discarded
+pub const SAVE_VERSION: u32 = 1;
+
+/// A game in progress: playfield, falling block (incl. rotation), queue and randomizer, hold,
+/// score (part of the game) and level (gravity)
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveGame {
+    version: u32,
+    pub mode: GameMode,
+    pub game: Tetris,
+    pub timer: TickTimer,
+}
+
+impl SaveGame {
+    pub fn new(mode: GameMode, game: Tetris, timer: TickTimer) -> Self {
+        Self { version: SAVE_VERSION, mode, game, timer }
+    }
+
+    /// Whether the save was written with the current save format
+    pub fn is_compatible(&self) -> bool {
+        self.version == SAVE_VERSION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::GameCmd;
+
+    #[test]
+    fn saved_games_continue_where_they_left_off() {
+        let mut game = Tetris::new(10, 20, 5);
+        for _ in 0..6 {
+            game.execute(&GameCmd::HardDrop);
+        }
+        let save = SaveGame::new(GameMode::Practice, game.clone(), TickTimer::with_rules(game.rules(), 1));
+        let json = serde_json::to_string(&save).unwrap();
+        let mut loaded: SaveGame = serde_json::from_str(&json).unwrap();
+        assert!(loaded.is_compatible());
+        assert_eq!(loaded.mode, GameMode::Practice);
+
+        // Same board and randomizer, so the games go on the same way
+        for _ in 0..6 {
+            game.execute(&GameCmd::HardDrop);
+            loaded.game.execute(&GameCmd::HardDrop);
+        }
+        assert_eq!(loaded.game.playfield(), game.playfield());
+        assert_eq!(loaded.game.score(), game.score());
+        assert_eq!(loaded.game.current_block().id(), game.current_block().id());
+    }
+
+    #[test]
+    fn saves_of_other_versions_are_incompatible() {
+        let game = Tetris::new(10, 20, 5);
+        let save = SaveGame { version: SAVE_VERSION + 1, mode: GameMode::Marathon, timer: TickTimer::with_rules(game.rules(), 1), game };
+        assert!(!save.is_compatible());
+    }
+}
@@ -1,30 +1,81 @@
+use serde::{Serialize, Deserialize};
+
+use super::enums::Ruleset;
+
 const SCORE_0_LINE: usize = 0;
 const SCORE_1_LINE: usize = 11;
 const SCORE_2_LINE: usize = 29;
 const SCORE_3_LINE: usize = 67;
 const SCORE_4_LINE: usize = 103;
+// Every row beyond four (pieces larger than tetrominoes) is worth as much as the fourth
+const SCORE_EXTRA_LINE: usize = SCORE_4_LINE - SCORE_3_LINE;
 
+// Points of the NES, multiplied by the level plus one
+const NES_SCORES: [usize; 5] = [0, 40, 100, 300, 1200];
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Scoreboard {
     score: usize,
+    lines: usize,
+    rules: Ruleset,
 }
 
 impl Scoreboard {
     pub fn new() -> Self {
-        Self { score: 0 }
+        Self::with_rules(Ruleset::Modern)
     }
 
-    pub fn update_score(&mut self, lines_cleared: usize) {
-        self.score += match lines_cleared {
-            0 => SCORE_0_LINE,
-            1 => SCORE_1_LINE,
-            2 => SCORE_2_LINE,
-            3 => SCORE_3_LINE,
-            4 => SCORE_4_LINE,
-            _ => panic!("Not implemented more than four line tetris"),
+    pub fn with_rules(rules: Ruleset) -> Self {
+        Self { score: 0, lines: 0, rules }
+    }
+
+    /// Scores a lock which cleared `lines_cleared` rows at `level`
+    pub fn update_score(&mut self, lines_cleared: usize, level: usize) {
+        self.lines += lines_cleared;
+        self.score += match self.rules {
+            Ruleset::Modern => match lines_cleared {
+                0 => SCORE_0_LINE,
+                1 => SCORE_1_LINE,
+                2 => SCORE_2_LINE,
+                3 => SCORE_3_LINE,
+                4 => SCORE_4_LINE,
+                n => SCORE_4_LINE + (n - 4) * SCORE_EXTRA_LINE,
+            },
+            Ruleset::Nes => (level + 1) * match lines_cleared {
+                n @ 0..=4 => NES_SCORES[n],
+                n => NES_SCORES[4] + (n - 4) * (NES_SCORES[4] - NES_SCORES[3]),
+            },
         };
     }
 
     pub fn get_score(&self) -> usize {
         self.score
     }
+
+    /// Rows cleared so far
+    pub fn get_lines(&self) -> usize {
+        self.lines
+    }
+}
+
+impl Default for Scoreboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nes_scores_by_level() {
+        let mut scoreboard = Scoreboard::with_rules(Ruleset::Nes);
+        for (lines, level, score) in [(1, 0, 40), (2, 0, 100), (3, 0, 300), (4, 0, 1200), (4, 9, 12000), (0, 19, 0), (1, 19, 800)] {
+            let before = scoreboard.get_score();
+            scoreboard.update_score(lines, level);
+            assert_eq!(scoreboard.get_score() - before, score, "{} lines at level {}", lines, level);
+        }
+        assert_eq!(scoreboard.get_lines(), 15);
+    }
 }
@@ -0,0 +1,337 @@
+use serde::{Serialize, Deserialize};
+
+use super::tetris::{Tetris, LockInfo};
+use super::events::{GameEvent, GameObserver};
+
+// Garbage lines sent per clear, indexed by the number of lines (T-spins separately)
+const ATTACK: [usize; 5] = [0, 0, 1, 2, 4];
+const ATTACK_TSPIN: [usize; 4] = [0, 2, 4, 6];
+const ATTACK_COMBO: [usize; 12] = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4];
+const ATTACK_COMBO_MAX: usize = 5;
+const ATTACK_BACK_TO_BACK: usize = 1;
+const ATTACK_PERFECT_CLEAR: usize = 10;
+
+const CSV_HEADER: &str = "pieces,time_s,pps,apm,kpp,keys,attack,lines,singles,doubles,triples,tetrises,\
+tspins,tspin_singles,tspin_doubles,tspin_triples,max_combo,holes";
+
+/// Statistics of a single game
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GameStats {
+    pub pieces: usize,
+    pub time_ms: i64,
+    pub keys: usize,
+    pub attack: usize,
+    pub lines: usize,
+    pub singles: usize,
+    pub doubles: usize,
+    pub triples: usize,
+    // Four or more lines
+    pub tetrises: usize,
+    // T-spins without a line clear, and with one to three lines
+    pub tspins: usize,
+    pub tspin_singles: usize,
+    pub tspin_doubles: usize,
+    pub tspin_triples: usize,
+    pub max_combo: usize,
+    // Holes created by placements (holes that are uncovered again are not subtracted)
+    pub holes: usize,
+    // Placed pieces by name, in the order of the piece set (pieces of the same name in different sets add up)
+    pub distribution: Vec<(String, usize)>,
+
+    #[serde(skip)]
+    combo: Option<usize>,
+    #[serde(skip)]
+    back_to_back: bool,
+    #[serde(skip)]
+    holes_on_board: usize,
+}
+
+impl GameStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time spent playing
+    pub fn add_time(&mut self, ms: i64) {
+        self.time_ms += ms;
+    }
+
+    /// A command issued by the player
+    pub fn add_key(&mut self) {
+        self.keys += 1;
+    }
+
+    /// Book keeping after the last block of `game` locked
+    pub fn lock(&mut self, game: &Tetris) {
+        let Some(&LockInfo { id, lines, tspin, perfect_clear }) = game.last_lock() else { return };
+
+        self.pieces += 1;
+        self.lines += lines;
+        if let Some(piece) = game.pieces().get(id) {
+            *self.count_of(&piece.name) += 1;
+        }
+
+        let attack = match (tspin, lines) {
+            (true, 0)   => { self.tspins += 1; 0 },
+            (true, 1)   => { self.tspin_singles += 1; ATTACK_TSPIN[1] },
+            (true, 2)   => { self.tspin_doubles += 1; ATTACK_TSPIN[2] },
+            (true, _)   => { self.tspin_triples += 1; ATTACK_TSPIN[3] },
+            (false, 0)  => 0,
+            (false, 1)  => { self.singles += 1; ATTACK[1] },
+            (false, 2)  => { self.doubles += 1; ATTACK[2] },
+            (false, 3)  => { self.triples += 1; ATTACK[3] },
+            (false, _)  => { self.tetrises += 1; ATTACK[4] },
+        };
+
+        if lines > 0 {
+            let combo = self.combo.map_or(0, |combo| combo + 1);
+            let difficult = tspin || lines >= 4;
+            self.attack += attack
+                + ATTACK_COMBO.get(combo).copied().unwrap_or(ATTACK_COMBO_MAX)
+                + if difficult && self.back_to_back { ATTACK_BACK_TO_BACK } else { 0 }
+                + if perfect_clear { ATTACK_PERFECT_CLEAR } else { 0 };
+            self.max_combo = self.max_combo.max(combo);
+            self.combo = Some(combo);
+            self.back_to_back = difficult;
+        } else {
+            self.combo = None;
+        }
+
+        let holes_on_board = count_holes(game);
+        self.holes += holes_on_board.saturating_sub(self.holes_on_board);
+        self.holes_on_board = holes_on_board;
+    }
+
+    /// Holes are counted relative to the board of `game`, e.g., after a position was set up.
+    /// The distribution lists every piece of its set from here on, placed or not
+    pub fn set_board(&mut self, game: &Tetris) {
+        self.holes_on_board = count_holes(game);
+        for piece in &game.pieces().pieces {
+            self.count_of(&piece.name);
+        }
+    }
+
+    // Placed pieces of the name, a new entry is added at the end
+    fn count_of(&mut self, name: &str) -> &mut usize {
+        let i = match self.distribution.iter().position(|(n, _)| n == name) {
+            Some(i) => i,
+            None => {
+                self.distribution.push((name.to_string(), 0));
+                self.distribution.len() - 1
+            },
+        };
+        &mut self.distribution[i].1
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Pieces per second
+    pub fn pps(&self) -> f32 {
+        per_minute(self.pieces, self.time_ms) / 60.0
+    }
+
+    /// Attack per minute
+    pub fn apm(&self) -> f32 {
+        per_minute(self.attack, self.time_ms)
+    }
+
+    /// Keys per piece
+    pub fn kpp(&self) -> f32 {
+        match self.pieces {
+            0 => 0.0,
+            n => self.keys as f32 / n as f32,
+        }
+    }
+
+    /// Adds the counts of `other`, the max combo is the best of both
+    pub fn merge(&mut self, other: &GameStats) {
+        self.pieces        += other.pieces;
+        self.time_ms       += other.time_ms;
+        self.keys          += other.keys;
+        self.attack        += other.attack;
+        self.lines         += other.lines;
+        self.singles       += other.singles;
+        self.doubles       += other.doubles;
+        self.triples       += other.triples;
+        self.tetrises      += other.tetrises;
+        self.tspins        += other.tspins;
+        self.tspin_singles += other.tspin_singles;
+        self.tspin_doubles += other.tspin_doubles;
+        self.tspin_triples += other.tspin_triples;
+        self.max_combo      = self.max_combo.max(other.max_combo);
+        self.holes         += other.holes;
+        for (name, count) in &other.distribution {
+            *self.count_of(name) += count;
+        }
+    }
+
+    // Pieces are counted in the columns `names`
+    fn csv_row(&self, names: &[&str]) -> String {
+        let distribution = names.iter()
+            .map(|&name| self.distribution.iter().find(|(n, _)| n == name).map_or(0, |&(_, count)| count));
+        let counts = [
+            self.keys, self.attack, self.lines(), self.singles, self.doubles, self.triples, self.tetrises,
+            self.tspins, self.tspin_singles, self.tspin_doubles, self.tspin_triples, self.max_combo, self.holes,
+        ];
+        format!("{},{:.3},{:.3},{:.3},{:.3},{}",
+            self.pieces,
+            self.time_ms as f32 / 1000.0,
+            self.pps(),
+            self.apm(),
+            self.kpp(),
+            counts.into_iter().chain(distribution).map(|n| n.to_string()).collect::<Vec<_>>().join(","))
+    }
+}
+
+impl GameObserver for GameStats {
+    fn notify(&mut self, event: &GameEvent, game: &Tetris) {
+        if let GameEvent::Locked { .. } = event {
+            self.lock(game);
+        }
+    }
+}
+
+/// Statistics of every game played since the app started
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    games: Vec<GameStats>,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep a finished (or abandoned) game, games without a single placed block are dropped
+    pub fn add(&mut self, game: GameStats) {
+        if game.pieces > 0 {
+            self.games.push(game);
+        }
+    }
+
+    pub fn games(&self) -> &[GameStats] {
+        &self.games
+    }
+
+    /// All games of the session added up, including `current`
+    pub fn total(&self, current: &GameStats) -> GameStats {
+        let mut total = current.clone();
+        self.games.iter().for_each(|game| total.merge(game));
+        total
+    }
+
+    /// One row per game, including `current`, with a column for every piece name of the games
+    pub fn to_csv(&self, current: &GameStats) -> String {
+        let mut names: Vec<&str> = vec![];
+        for (name, _) in self.games.iter().chain([current]).flat_map(|game| &game.distribution) {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+
+        let mut csv = String::from(CSV_HEADER);
+        names.iter().for_each(|name| csv.push_str(&format!(",{}", name.to_lowercase())));
+        for game in self.games.iter().chain([current]) {
+            csv.push('\n');
+            csv.push_str(&game.csv_row(&names));
+        }
+        csv
+    }
+
+    /// Array of games, including `current`
+    pub fn to_json(&self, current: &GameStats) -> String {
+        let games: Vec<&GameStats> = self.games.iter().chain([current]).collect();
+        serde_json::to_string_pretty(&games).unwrap_or_default()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+fn per_minute(count: usize, ms: i64) -> f32 {
+    match ms {
+        ms if ms <= 0 => 0.0,
+        ms => count as f32 * 60_000.0 / ms as f32,
+    }
+}
+
+// Empty cells with a filled cell somewhere above them in the same column
+fn count_holes(game: &Tetris) -> usize {
+    let field = game.playfield();
+    (0..game.width() as usize)
+        .map(|x| field.iter()
+            .map(|row| row[x])
+            .skip_while(Option::is_none)
+            .filter(Option::is_none)
+            .count())
+        .sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::GameCmd;
+    use crate::pieces::PieceSet;
+
+    // Stats of `drops` hard dropped blocks of a fresh game with the pieces
+    fn stats_of(pieces: PieceSet, drops: usize) -> GameStats {
+        let mut game = Tetris::with_pieces(10, 20, 1, pieces);
+        let mut stats = GameStats::new();
+        stats.set_board(&game);
+        for _ in 0..drops {
+            game.execute(&GameCmd::HardDrop);
+            stats.lock(&game);
+        }
+        stats
+    }
+
+    #[test]
+    fn distribution_counts_every_piece_of_the_set() {
+        let pentominoes = PieceSet::find("Pentomino").unwrap();
+        let stats = stats_of(pentominoes.clone(), 3);
+        let names: Vec<&str> = stats.distribution.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, pentominoes.pieces.iter().map(|piece| piece.name.as_str()).collect::<Vec<_>>());
+        assert_eq!(stats.distribution.iter().map(|&(_, n)| n).sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn csv_has_a_column_per_piece() {
+        let mut session = SessionStats::new();
+        session.add(stats_of(PieceSet::tetrominoes(), 2));
+        let csv = session.to_csv(&GameStats::new());
+        let header = csv.lines().next().unwrap();
+        assert!(header.ends_with(",holes,i,j,l,o,s,t,z"), "{}", header);
+        assert!(csv.lines().all(|row| row.split(',').count() == header.split(',').count()));
+
+        let csv = session.to_csv(&stats_of(PieceSet::find("Tromino+Tetromino").unwrap(), 1));
+        assert!(csv.lines().next().unwrap().ends_with(",i,j,l,o,s,t,z,i3,l3"));
+    }
+    #[test]
+    fn singles_build_a_combo() {
+        use crate::enums::BlockID;
+        use crate::util::Coord;
+
+        // A flat I fills a row of a board as wide as it, the cell below keeps the board from clearing
+        let mut game = Tetris::new(4, 20, 1);
+        game.set_cell(Coord(1, 19), Some(BlockID::O));
+        let mut stats = GameStats::new();
+        stats.set_board(&game);
+        for _ in 0..4 {
+            game.spawn_current_block(BlockID::I);
+            game.execute(&GameCmd::HardDrop);
+            stats.lock(&game);
+            stats.add_key();
+            stats.add_key();
+        }
+        stats.add_time(2000);
+
+        assert_eq!((stats.pieces, stats.lines(), stats.singles), (4, 4, 4));
+        assert_eq!(stats.max_combo, 3);
+        assert_eq!(stats.attack, ATTACK_COMBO[1] + ATTACK_COMBO[2] + ATTACK_COMBO[3]);
+        assert_eq!(stats.holes, 0);
+        assert_eq!((stats.pps(), stats.kpp()), (2.0, 2.0));
+    }
+}
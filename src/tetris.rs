@@ -1,26 +1,149 @@
+use std::fmt;
 use std::mem;
 use std::collections::HashSet;
 
+use serde::{Serialize, Deserialize};
+
 use super::block::{Block, BlockGenerator};
-use super::enums::{ShiftCmd, RotateCmd, GameStatus};
+use super::enums::{BlockID, ShiftCmd, RotateCmd, GameCmd, GameStatus, Phase, TopOut, Ruleset};
+use super::events::{self, GameEvent, GameObserver, EventQueue};
+use super::history::History;
+use super::pieces::PieceSet;
+use super::rotation::RotationSystem;
+use super::scoreboard::Scoreboard;
+use super::timer::LVL_UP;
 use super::util::Coord;
 
+/// Rows above the visible board, blocks may stick out into them but a block that locks
+/// entirely up there ends the game (lock out)
+pub const BUFFER_HEIGHT: i32 = 20;
+
+// Smallest and largest board (width and height)
+pub const MIN_SIZE: i32 = 4;
+pub const MAX_SIZE: i32 = 100;
+
+/// What happened when the last block locked
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub id: BlockID,
+    pub lines: usize,
+    // T rotated into place with at least three of the four corners around its center occupied
+    pub tspin: bool,
+    // The board is empty after the clear
+    pub perfect_clear: bool,
+}
+
+/// Delays (in milliseconds) between locking a block and the next block appearing, 0 skips the phase
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Delays {
+    // Entry delay (ARE), after the lock and after the line clear
+    pub entry_ms: i64,
+    // Full rows are shown this long before they are removed
+    pub line_clear_ms: i64,
+}
+
+/// Rotation and hold given while no block falls (line clear and entry delay), applied to the next block
+/// as it spawns: initial hold (IHS) first, then initial rotation (IRS)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InitialInput {
+    pub rotate: Option<RotateCmd>,
+    pub hold: bool,
+}
+
+impl InitialInput {
+    /// Whether the command is buffered already
+    pub fn contains(&self, cmd: &GameCmd) -> bool {
+        match cmd {
+            GameCmd::Rotate(rotate) => self.rotate == Some(*rotate),
+            GameCmd::Hold => self.hold,
+            _ => false,
+        }
+    }
+}
+
+/// The board is smaller than `MIN_SIZE` or larger than `MAX_SIZE` in some direction,
+/// or narrower than the widest piece (`piece_width`) it is played with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoardSizeError {
+    pub width: i32,
+    pub height: i32,
+    pub piece_width: i32,
+}
+
+impl fmt::Display for BoardSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.width < self.piece_width && (MIN_SIZE..=MAX_SIZE).contains(&self.width) {
+            return write!(f, "a board {} cells wide is too narrow for pieces {} cells wide", self.width, self.piece_width)
+        }
+        write!(f, "a board of {} x {} cells is not supported, width and height have to be between {} and {}",
+            self.width, self.height, MIN_SIZE, MAX_SIZE)
+    }
+}
+
+impl std::error::Error for BoardSizeError {}
+
+// Everything that changes from one block to the next
+#[derive(Clone)]
+struct Snapshot {
+    state: Vec<Block>,
+    current_block: Block,
+    hold_block: Option<Block>,
+    hold_used: bool,
+    block_generator: BlockGenerator,
+    scoreboard: Scoreboard,
+    phase: Phase,
+    locked_out: bool,
+}
+
+// Snapshots taken whenever a block locks, `checkpoint` is the position the current block started from
+#[derive(Clone)]
+struct Undo {
+    history: History<Snapshot>,
+    checkpoint: Snapshot,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Tetris {
     width: i32,
     height: i32,
+    pieces: PieceSet,
+    rotation: RotationSystem,
+    rules: Ruleset,
     state: Vec<Block>,
     boundary: HashSet<Coord>,
     current_block: Block,
+    hold_block: Option<Block>,
+    hold_used: bool,
     block_generator: BlockGenerator,
+    scoreboard: Scoreboard,
+    last_rotated: bool,
+    last_lock: Option<LockInfo>,
+    phase: Phase,
+    delays: Delays,
+    locked_out: bool,
+    initial: InitialInput,
+    start_level: usize,
+    #[serde(skip)]
+    events: EventQueue,
+    #[serde(skip)]
+    undo: Option<Box<Undo>>,
 }
 
 impl Tetris {
-    pub fn new(width: i32, height: i32) -> Self {
-        assert!(width >= 4 && height >= 4);
+    /// A game of tetrominoes, panics if the size is invalid, see `check_size`
+    pub fn new(width: i32, height: i32, seed: u64) -> Self {
+        Self::with_pieces(width, height, seed, PieceSet::tetrominoes())
+    }
+
+    /// A game dealing the pieces of the set, panics if the size is invalid, see `check_size`
+    pub fn with_pieces(width: i32, height: i32, seed: u64, pieces: PieceSet) -> Self {
+        if let Err(e) = Self::check_size(width, height, &pieces) {
+            panic!("{}", e);
+        }
 
         // Walls on the outside of the tetris court
         let mut boundary: HashSet<Coord> = HashSet::with_capacity((height*2 + width + 2) as usize);
-        for i in -2..=height {
+        for i in -BUFFER_HEIGHT..=height {
             boundary.insert(Coord(0, i));
             boundary.insert(Coord(width+1, i));
         }
@@ -28,19 +151,194 @@ impl Tetris {
             boundary.insert(Coord(i, height));
         }
 
-        // Generate first block and center it
-        let mut gen = BlockGenerator::new();
-        let first_block = gen.next().unwrap();
-        let first_block = &first_block + Coord(1 + ((width - first_block.width()) as f32 / 2.0) as i32, 0);
+        // Generate first block, it is centered below
+        let mut gen = BlockGenerator::new(seed, pieces.len());
+        let rotation = RotationSystem::default();
+        let first_block = rotation.spawn(&pieces, gen.next().unwrap());
 
-        Self {
+        let mut game = Self {
             width,
             height,
+            pieces,
+            rotation,
+            rules: Ruleset::Modern,
             boundary,
             state: vec![],
-            current_block: first_block,
+            current_block: first_block.clone(),
+            hold_block: None,
+            hold_used: false,
             block_generator: gen,
+            scoreboard: Scoreboard::new(),
+            last_rotated: false,
+            last_lock: None,
+            phase: Phase::Falling,
+            delays: Delays::default(),
+            locked_out: false,
+            initial: InitialInput::default(),
+            start_level: 1,
+            events: EventQueue::default(),
+            undo: None,
+        };
+        game.spawn(*first_block.id());
+        game
+    }
+
+    /// Whether a board of the size can be played on with the pieces
+    pub fn check_size(width: i32, height: i32, pieces: &PieceSet) -> Result<(), BoardSizeError> {
+        let piece_width = pieces.max_width();
+        let valid = (MIN_SIZE..=MAX_SIZE).contains(&width) && (MIN_SIZE..=MAX_SIZE).contains(&height)
+            && width >= piece_width;
+        if valid { Ok(()) } else { Err(BoardSizeError { width, height, piece_width }) }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.clone(),
+            current_block: self.current_block.clone(),
+            hold_block: self.hold_block.clone(),
+            hold_used: self.hold_used,
+            block_generator: self.block_generator.clone(),
+            scoreboard: self.scoreboard.clone(),
+            phase: self.phase.clone(),
+            locked_out: self.locked_out,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.state = snapshot.state;
+        self.current_block = snapshot.current_block;
+        self.hold_block = snapshot.hold_block;
+        self.hold_used = snapshot.hold_used;
+        self.block_generator = snapshot.block_generator;
+        self.scoreboard = snapshot.scoreboard;
+        self.phase = snapshot.phase;
+        self.locked_out = snapshot.locked_out;
+        self.initial = InitialInput::default();
+    }
+
+    // The current position becomes the one the current block started from
+    fn update_checkpoint(&mut self) {
+        if self.undo.is_some() {
+            let snapshot = self.snapshot();
+            if let Some(undo) = &mut self.undo {
+                undo.checkpoint = snapshot;
+            }
+        }
+    }
+
+    /// Remember the last `capacity` locked blocks so they can be taken back,
+    /// `None` turns undo off and forgets the history
+    pub fn set_undo_limit(&mut self, capacity: Option<usize>) {
+        self.undo = capacity.map(|capacity| Box::new(Undo {
+            history: History::new(capacity),
+            checkpoint: self.snapshot(),
+        }));
+    }
+
+    /// Remembers the current position before it is edited, so the edit can be undone
+    pub fn record_edit(&mut self) {
+        let snapshot = self.snapshot();
+        if let Some(undo) = &mut self.undo {
+            undo.history.record(snapshot);
+        }
+    }
+
+    /// Takes back the last locked block (or edit), including the score and the randomizer,
+    /// so the same blocks are dealt again. Returns whether anything was undone
+    pub fn undo(&mut self) -> bool {
+        let Some(undo) = &mut self.undo else { return false };
+        match undo.history.undo(undo.checkpoint.clone()) {
+            Some(snapshot) => {
+                undo.checkpoint = snapshot.clone();
+                self.restore(snapshot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Replays the last undone block (or edit). Returns whether anything was redone
+    pub fn redo(&mut self) -> bool {
+        let Some(undo) = &mut self.undo else { return false };
+        match undo.history.redo(undo.checkpoint.clone()) {
+            Some(snapshot) => {
+                undo.checkpoint = snapshot.clone();
+                self.restore(snapshot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.undo.as_ref().is_some_and(|undo| undo.history.can_undo())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.undo.as_ref().is_some_and(|undo| undo.history.can_redo())
+    }
+
+    /// The last block that locked, `None` until the first lock
+    pub fn last_lock(&self) -> Option<&LockInfo> {
+        self.last_lock.as_ref()
+    }
+
+    /// The events since the last call, oldest first
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        self.events.drain()
+    }
+
+    /// Hands the events since the last call to every observer, oldest first
+    pub fn dispatch_events(&mut self, observers: &mut [&mut dyn GameObserver]) {
+        let events = self.events.drain();
+        events::dispatch(&events, self, observers);
+    }
+
+    /// Level (starting at the start level), goes up every `LVL_UP` points like the gravity does.
+    /// Under NES rules it goes up every 10 lines, but only once enough lines for the start level are cleared
+    pub fn level(&self) -> usize {
+        match self.rules {
+            Ruleset::Modern => self.start_level + self.score() / LVL_UP as usize,
+            Ruleset::Nes => nes_level(self.start_level, self.lines()),
+        }
+    }
+
+    /// Level the game starts at, see `Ruleset::start_levels`
+    pub fn start_level(&self) -> usize {
+        self.start_level
+    }
+
+    pub fn set_start_level(&mut self, level: usize) {
+        let levels = self.rules.start_levels();
+        self.start_level = level.clamp(*levels.start(), *levels.end());
+    }
+
+    /// How the game scores, deals its blocks and speeds up
+    pub fn rules(&self) -> Ruleset {
+        self.rules
+    }
+
+    /// Switches the rules, meant for fresh games since the score starts over and the current block is dealt again
+    pub fn set_rules(&mut self, rules: Ruleset) {
+        self.rules = rules;
+        self.scoreboard = Scoreboard::with_rules(rules);
+        self.block_generator.set_rules(rules);
+        if let Some(id) = self.block_generator.next() {
+            self.spawn(id);
         }
+        self.hold_block = None;
+        self.set_start_level(self.start_level);
+        self.update_checkpoint();
+    }
+
+    /// Points scored so far
+    pub fn score(&self) -> usize {
+        self.scoreboard.get_score()
+    }
+
+    /// Rows cleared so far
+    pub fn lines(&self) -> usize {
+        self.scoreboard.get_lines()
     }
 
     fn block_outside_bounds(&self, block: &Block) -> bool {
@@ -48,27 +346,82 @@ impl Tetris {
     }
 
     fn block_collision(&self, block: &Block) -> bool {
-        self.state.iter().any(|b| b.is_collision(&block))
+        self.state.iter().any(|b| b.is_collision(block))
+    }
+
+    /// Whether the block fits inside the walls without overlapping the state
+    pub fn is_feasible(&self, block: &Block) -> bool {
+        !self.block_outside_bounds(block) && !self.block_collision(block)
+    }
+
+    /// Executes a player command, returns the number of cleared lines if the command locked the block.
+    /// While no block falls, rotations and hold are kept for the next block (see `InitialInput`) and the rest is dropped
+    pub fn execute(&mut self, cmd: &GameCmd) -> Option<usize> {
+        if self.phase != Phase::Falling {
+            match cmd {
+                GameCmd::Rotate(rotate) if self.rules.has_initial_input() => self.initial.rotate = Some(*rotate),
+                GameCmd::Hold if self.rules.has_initial_input() => self.initial.hold = true,
+                _ => (),
+            }
+            return None
+        }
+        match cmd {
+            GameCmd::Shift(shift) => { self.shift_block_if_feasible(shift); None },
+            GameCmd::Rotate(rotate) => { self.rotate_block_if_feasible(rotate); None },
+            GameCmd::SoftDrop => self.tick(),
+            GameCmd::HardDrop => self.hard_drop(),
+            GameCmd::Hold => { self.hold_block_if_feasible(); None },
+        }
     }
 
     /// todo!()
     pub fn shift_block_if_feasible(&mut self, cmd: &ShiftCmd) {
-        let shifted_block = self.current_block.shifted_version(&cmd);
-        if !self.block_outside_bounds(&shifted_block)
-            && !self.block_collision(&shifted_block)
-        {
+        let shifted_block = self.current_block.shifted_version(cmd);
+        if self.is_feasible(&shifted_block) {
             self.current_block = shifted_block;
+            self.last_rotated = false;
+            self.events.push(GameEvent::Moved { shift: *cmd });
         }
     }
 
-    /// todo!()
+    /// Turns the current block, kicked to the first place it fits by the rotation system
     pub fn rotate_block_if_feasible(&mut self, cmd: &RotateCmd) {
-        let rotated_block = self.current_block.rotated_version(&cmd);
-        if !self.block_outside_bounds(&rotated_block)
-            && !self.block_collision(&rotated_block)
-        {
+        if let Some((rotated_block, kick)) = self.rotated(&self.current_block, cmd) {
             self.current_block = rotated_block;
+            self.last_rotated = true;
+            self.events.push(GameEvent::Rotated { rotate: *cmd, kick });
+        }
+    }
+
+    /// The block turned by `cmd` and the kick it took, `None` if it doesn't fit anywhere or the rules forbid the turn
+    pub fn rotated(&self, block: &Block, cmd: &RotateCmd) -> Option<(Block, Coord)> {
+        if *cmd == RotateCmd::Half && !self.rules.has_half_turn() {
+            return None
+        }
+        self.rotation.rotate(&self.pieces, block, cmd, |block| self.is_feasible(block))
+    }
+
+    /// Swaps the current block with the held one (or the next block if nothing is held).
+    /// Only allowed once per locked block, and not at all under NES rules.
+    pub fn hold_block_if_feasible(&mut self) {
+        if !self.can_hold() || self.phase != Phase::Falling {
+            return
         }
+
+        let swap_in = match self.hold_block.take() {
+            Some(block) => block,
+            None => match self.block_generator.next() {
+                Some(id) => self.rotation.spawn(&self.pieces, id),
+                None => return,
+            },
+        };
+        let swap_in = self.center_block(&swap_in);
+        let swap_out = mem::replace(&mut self.current_block, swap_in);
+        self.events.push(GameEvent::Held { id: *swap_out.id() });
+        self.events.push(GameEvent::Spawned { id: *self.current_block.id() });
+        self.hold_block = Some(self.rotation.spawn(&self.pieces, *swap_out.id()));
+        self.hold_used = true;
+        self.last_rotated = false;
     }
 
     /// todo!()
@@ -110,7 +463,50 @@ impl Tetris {
 
     /// todo!()
     pub fn peek_next_block(&self) -> Option<Block> {
-        self.block_generator.peek_next()
+        self.block_generator.peek_next().map(|id| self.rotation.spawn(&self.pieces, id))
+    }
+
+    /// The next `n` blocks in the queue
+    pub fn peek_queue(&self, n: usize) -> Vec<Block> {
+        self.block_generator.peek_queue(n).into_iter().map(|id| self.rotation.spawn(&self.pieces, id)).collect()
+    }
+
+    /// How the blocks of the game turn
+    pub fn rotation(&self) -> RotationSystem {
+        self.rotation
+    }
+
+    /// Switches the rotation system, meant for fresh games since the current and held block spawn again
+    pub fn set_rotation(&mut self, rotation: RotationSystem) {
+        self.rotation = rotation;
+        self.spawn(*self.current_block.id());
+        self.hold_block = self.hold_block.as_ref().map(|block| rotation.spawn(&self.pieces, *block.id()));
+        self.update_checkpoint();
+    }
+
+    /// The pieces the game is dealt
+    pub fn pieces(&self) -> &PieceSet {
+        &self.pieces
+    }
+
+    /// The block currently held, in its spawn orientation
+    pub fn held_block(&self) -> Option<&Block> {
+        self.hold_block.as_ref()
+    }
+
+    /// Whether hold is available for the current block
+    pub fn can_hold(&self) -> bool {
+        !self.hold_used && self.rules.has_hold()
+    }
+
+    /// Width of the playable area
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Height of the playable area
+    pub fn height(&self) -> i32 {
+        self.height
     }
 
     /// todo!()
@@ -118,6 +514,74 @@ impl Tetris {
         self.state.iter()
     }
 
+    /// The locked cells as rows (top to bottom) of columns (left to right) within the walls
+    pub fn playfield(&self) -> Vec<Vec<Option<BlockID>>> {
+        let mut field = vec![vec![None; self.width as usize]; self.height as usize];
+        for block in &self.state {
+            for coord in block.config() {
+                if (0..self.height).contains(&coord.1) {
+                    field[coord.1 as usize][(coord.0 - 1) as usize] = Some(*block.id());
+                }
+            }
+        }
+        field
+    }
+
+    /// Replaces the locked cells with `field`, given as rows (top to bottom) of columns (left to right)
+    /// where the last row is the bottom of the board. Rows beyond the height end up above the board.
+    pub fn set_playfield(&mut self, field: &[Vec<Option<BlockID>>]) {
+        let top = self.height - field.len() as i32;
+        self.state = field.iter()
+            .zip(top..)
+            .flat_map(|(row, y)| row.iter()
+                .take(self.width as usize)
+                .zip(1..)
+                .filter_map(move |(cell, x)| cell.map(|id| Block::cell(id, Coord(x, y)))))
+            .collect();
+        self.update_checkpoint();
+    }
+
+    /// Paints (or erases if `id` is `None`) a single cell of the playfield
+    pub fn set_cell(&mut self, coord: Coord, id: Option<BlockID>) {
+        self.state.iter_mut().for_each(|block| block.remove_cell(&coord));
+        self.state.retain(|block| !block.is_fully_cleared());
+        if let Some(id) = id {
+            self.state.push(Block::cell(id, coord));
+        }
+        self.update_checkpoint();
+    }
+
+    /// Replaces the falling block, e.g., when setting up a position
+    pub fn set_current_block(&mut self, block: Block) {
+        self.current_block = block;
+        self.update_checkpoint();
+    }
+
+    /// Replaces the falling block with a freshly spawned block of type `id`
+    pub fn spawn_current_block(&mut self, id: BlockID) {
+        self.spawn(id);
+        self.update_checkpoint();
+    }
+
+    // Puts a block of type `id` where blocks spawn, observers learn about every block that enters the board
+    fn spawn(&mut self, id: BlockID) {
+        self.current_block = self.center_block(&self.rotation.spawn(&self.pieces, id));
+        self.events.push(GameEvent::Spawned { id });
+    }
+
+    /// Blocks (first element first) to deal before the randomizer takes over again
+    pub fn set_queue(&mut self, queue: &[BlockID]) {
+        self.block_generator.set_queue(queue);
+        self.update_checkpoint();
+    }
+
+    /// Replaces the held block, which can be used immediately
+    pub fn set_held_block(&mut self, id: Option<BlockID>) {
+        self.hold_block = id.map(|id| self.rotation.spawn(&self.pieces, id));
+        self.hold_used = false;
+        self.update_checkpoint();
+    }
+
     /// todo!()
     pub fn boundary_config(&self) -> impl Iterator<Item=&Coord> {
         self.boundary.iter()
@@ -125,24 +589,127 @@ impl Tetris {
 
     /// todo!()
     pub fn status(&self) -> GameStatus {
-        if self.block_collision(&self.current_block) {
-            GameStatus::GameOver
+        match self.top_out() {
+            Some(_) => GameStatus::GameOver,
+            None => GameStatus::Okay,
+        }
+    }
+
+    /// How the game was lost, if it is over
+    pub fn top_out(&self) -> Option<TopOut> {
+        if self.locked_out {
+            Some(TopOut::LockOut)
+        } else if self.phase == Phase::Falling && !self.is_feasible(&self.current_block) {
+            Some(TopOut::BlockOut)
         } else {
-            GameStatus::Okay
+            None
         }
     }
 
     /// todo!()
     /// returns whether the game is lost or not
     pub fn tick(&mut self) -> Option<usize> {
+        if self.phase != Phase::Falling {
+            return None
+        }
         self.drop_n(1)
     }
 
     /// todo!()
     pub fn hard_drop(&mut self) -> Option<usize> {
+        if self.phase != Phase::Falling {
+            return None
+        }
         self.drop_n(self.height+2)
     }
 
+    /// Lets `elapsed_ms` pass in the line clear and entry delays, returns whether the next block appeared
+    pub fn update(&mut self, elapsed_ms: i64) -> bool {
+        match &mut self.phase {
+            Phase::Falling => false,
+            Phase::LineClear { remaining_ms, .. } => {
+                *remaining_ms -= elapsed_ms;
+                if *remaining_ms <= 0 {
+                    self.clear_filled_lines();
+                    self.enter()
+                } else {
+                    false
+                }
+            },
+            Phase::Entry { remaining_ms } => {
+                *remaining_ms -= elapsed_ms;
+                if *remaining_ms <= 0 {
+                    self.spawn_next_block();
+                    true
+                } else {
+                    false
+                }
+            },
+        }
+    }
+
+    /// Whether the game is falling, clearing lines or waiting for the next block
+    pub fn phase(&self) -> &Phase {
+        &self.phase
+    }
+
+    /// Rotation and hold waiting for the next block to spawn
+    pub fn initial_input(&self) -> InitialInput {
+        self.initial
+    }
+
+    pub fn delays(&self) -> Delays {
+        self.delays
+    }
+
+    /// Delays used from the next lock on
+    pub fn set_delays(&mut self, delays: Delays) {
+        self.delays = delays;
+    }
+
+    // Start the entry delay, or spawn right away without one. Returns whether the next block appeared
+    fn enter(&mut self) -> bool {
+        if self.delays.entry_ms > 0 {
+            self.phase = Phase::Entry { remaining_ms: self.delays.entry_ms };
+            false
+        } else {
+            self.spawn_next_block();
+            true
+        }
+    }
+
+    fn spawn_next_block(&mut self) {
+        if let Some(id) = self.block_generator.next() {
+            self.current_block = self.center_block(&self.rotation.spawn(&self.pieces, id));
+        }
+        self.phase = Phase::Falling;
+        if !self.is_feasible(&self.current_block) {
+            self.events.push(GameEvent::TopOut { kind: TopOut::BlockOut });
+        } else {
+            self.events.push(GameEvent::Spawned { id: *self.current_block.id() });
+            let initial = mem::take(&mut self.initial);
+            self.apply_initial_input(initial);
+        }
+
+        // The next block starts from here
+        self.update_checkpoint();
+    }
+
+    // The held block swaps in first, then the spawned block turns in place unless that collides (no kicks)
+    fn apply_initial_input(&mut self, initial: InitialInput) {
+        if initial.hold {
+            self.hold_block_if_feasible();
+        }
+        if let Some(rotate) = initial.rotate {
+            // Every kick table starts in place
+            let turned = self.rotated(&self.current_block, &rotate).filter(|&(_, kick)| kick == Coord(0, 0));
+            if let Some((turned, kick)) = turned {
+                self.current_block = turned;
+                self.events.push(GameEvent::Rotated { rotate, kick });
+            }
+        }
+    }
+
     // aux function for tick and hard_drop (n = how many blocks we should drop maximally)
     fn drop_n(&mut self, n: i32) -> Option<usize> {
         assert!(n > 0);
@@ -150,29 +717,318 @@ impl Tetris {
 
             let dropped_block = self.current_block.drop_one();
 
-            if self.block_outside_bounds(&dropped_block)
-                || self.block_collision(&dropped_block)
-            {
+            if !self.is_feasible(&dropped_block) {
                 // If dropped block is infeasible,
                 // add the current block to the tetris state and change current_block
-                if let Some(next_block) = self.block_generator.next() {
-                    let next_block = self.center_block(&next_block);
-                    let block_to_add = mem::replace(&mut self.current_block, next_block);
-                    self.state.push(block_to_add);
-                    let num_cleared = self.clear_filled_lines();
-
-                    return Some(num_cleared)
-                }
+                return Some(self.lock_current_block())
             } else {
                 self.current_block = dropped_block;
+                // Falling cancels a T-spin, but the hard drop locks right away
+                if n == 1 {
+                    self.last_rotated = false;
+                }
             }
         }
         None
     }
 
+    // Adds the current block to the state, then clears the full rows and spawns the next block,
+    // either right away or after the delays. Returns the number of cleared lines
+    fn lock_current_block(&mut self) -> usize {
+        // Undo returns to where the block started, also while the rows clear and the next block waits to appear
+        if let Some(undo) = &mut self.undo {
+            undo.history.record(undo.checkpoint.clone());
+        }
+
+        let tspin = self.is_tspin(&self.current_block);
+        let id = *self.current_block.id();
+        let locked_out = self.current_block.config().all(|coord| coord.1 < 0);
+        self.state.push(self.current_block.clone());
+        self.hold_used = false;
+        self.last_rotated = false;
+
+        // Nothing can be cleared by a block that is completely out of sight, and nothing spawns after it
+        if locked_out {
+            self.locked_out = true;
+            self.last_lock = Some(LockInfo { id, lines: 0, tspin, perfect_clear: false });
+            self.events.push(GameEvent::Locked { id, cells: self.current_block.config().copied().collect() });
+            self.events.push(GameEvent::TopOut { kind: TopOut::LockOut });
+            self.update_checkpoint();
+            return 0
+        }
+
+        let rows: Vec<usize> = (0..self.height as usize).filter(|&line| self.is_line_full(line)).collect();
+        let num_cleared = rows.len();
+        let num_cells: usize = self.state.iter().map(|block| block.config().count()).sum();
+        let perfect_clear = num_cleared > 0 && num_cells == num_cleared * self.width as usize;
+        self.last_lock = Some(LockInfo { id, lines: num_cleared, tspin, perfect_clear });
+
+        let level = self.level();
+        self.scoreboard.update_score(num_cleared, level);
+        self.events.push(GameEvent::Locked { id, cells: self.current_block.config().copied().collect() });
+        if num_cleared > 0 || tspin {
+            self.events.push(GameEvent::LinesCleared { rows: rows.clone(), tspin, perfect_clear });
+        }
+        if self.level() > level {
+            self.events.push(GameEvent::LevelUp { level: self.level() });
+        }
+
+        if num_cleared > 0 && self.delays.line_clear_ms > 0 {
+            self.phase = Phase::LineClear { rows, remaining_ms: self.delays.line_clear_ms };
+        } else {
+            self.clear_filled_lines();
+            self.enter();
+        }
+        self.update_checkpoint();
+        num_cleared
+    }
+
+    // Three corner rule, walls and floor count as occupied
+    fn is_tspin(&self, block: &Block) -> bool {
+        if self.pieces.tetromino(*block.id()) != Some(BlockID::T) || !self.last_rotated {
+            return false
+        }
+        let Coord(cx, cy) = block.center();
+        let occupied = [(-1, -1), (1, -1), (-1, 1), (1, 1)].into_iter()
+            .map(|(dx, dy)| Coord(cx + dx, cy + dy))
+            .filter(|&c| c.0 < 1 || c.0 > self.width || c.1 >= self.height
+                || self.state.iter().any(|b| b.config().any(|&x| x == c)))
+            .count();
+        occupied >= 3
+    }
+
+    // Centered at the top of the board, then moved by the spawn offset of the piece
     fn center_block(&self, block: &Block) -> Block {
         let half_block_width = block.width() as f32 / 2.0;
         let half_width = self.width as f32 / 2.0;
-        block + Coord(1 + (half_width - half_block_width) as i32, 0)
+        let spawn = self.pieces.get(*block.id()).map_or(Coord(0, 0), |piece| piece.spawn);
+        block + (Coord(1 + (half_width - half_block_width) as i32, 0) + spawn)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+// The NES leaves the start level after 10 lines per level (plus 10), but at most 100 lines
+// or 50 less than that, whichever is more. From then on every 10 lines is a level
+fn nes_level(start_level: usize, lines: usize) -> usize {
+    let transition = (start_level * 10 + 10).min((start_level * 10).saturating_sub(50).max(100));
+    if lines < transition {
+        start_level
+    } else {
+        start_level + 1 + (lines - transition) / 10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotation::Orientation;
+
+    fn spawned(game: &mut Tetris) -> Vec<BlockID> {
+        game.drain_events().into_iter()
+            .filter_map(|event| match event {
+                GameEvent::Spawned { id } => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tspins_without_lines_are_a_clear() {
+        let mut game = Tetris::new(10, 20, 1);
+        // A T pointing up, its arms one row above the floor
+        let mut block = &game.rotation().oriented(game.pieces(), BlockID::T, Orientation::Spawn) + Coord(4, 0);
+        while block.config().all(|c| c.1 < 18) {
+            block = &block + Coord(0, 1);
+        }
+        // Both cells below the arms and the one above the left arm are filled
+        let Coord(cx, cy) = block.center();
+        for corner in [Coord(cx - 1, cy + 1), Coord(cx + 1, cy + 1), Coord(cx - 1, cy - 1)] {
+            game.set_cell(corner, Some(BlockID::G));
+        }
+        game.set_current_block(block);
+        game.execute(&GameCmd::Rotate(RotateCmd::Right));
+        game.execute(&GameCmd::Rotate(RotateCmd::Left));
+        game.drain_events();
+        game.hard_drop();
+        let cleared = game.drain_events().into_iter().find(|event| matches!(event, GameEvent::LinesCleared { .. }));
+        assert_eq!(cleared, Some(GameEvent::LinesCleared { rows: vec![], tspin: true, perfect_clear: false }));
+    }
+
+    #[test]
+    fn every_spawn_is_an_event() {
+        let mut game = Tetris::new(10, 20, 1);
+        assert_eq!(spawned(&mut game), [*game.current_block().id()]);
+        game.set_rules(Ruleset::Nes);
+        assert_eq!(spawned(&mut game), [*game.current_block().id()]);
+        game.set_rotation(RotationSystem::Ars);
+        assert_eq!(spawned(&mut game), [*game.current_block().id()]);
+        game.spawn_current_block(BlockID::T);
+        assert_eq!(spawned(&mut game), [BlockID::T]);
+        game.hard_drop();
+        assert_eq!(spawned(&mut game), [*game.current_block().id()]);
+    }
+
+    #[test]
+    fn nes_level_transitions() {
+        // (start level, lines of the first level up)
+        for (start, transition) in [(0, 10), (5, 60), (9, 100), (10, 100), (15, 100), (16, 110), (18, 130), (19, 140)] {
+            assert_eq!(nes_level(start, transition - 1), start);
+            assert_eq!(nes_level(start, transition), start + 1);
+            assert_eq!(nes_level(start, transition + 9), start + 1);
+            assert_eq!(nes_level(start, transition + 10), start + 2);
+        }
+    }
+
+    #[test]
+    fn rejects_boards_narrower_than_pieces() {
+        let pentominoes = PieceSet::find("Pentomino").unwrap();
+        assert!(Tetris::check_size(4, 20, &PieceSet::tetrominoes()).is_ok());
+        assert_eq!(Tetris::check_size(4, 20, &pentominoes), Err(BoardSizeError { width: 4, height: 20, piece_width: 5 }));
+        assert!(Tetris::check_size(5, 20, &pentominoes).is_ok());
+    }
+
+    #[test]
+    fn undo_in_delays_takes_back_one_block() {
+        let mut game = Tetris::new(10, 20, 1);
+        game.set_undo_limit(Some(10));
+        game.set_delays(Delays { entry_ms: 100, line_clear_ms: 300 });
+        let first = game.current_block().clone();
+        game.hard_drop();
+        game.update(100);
+        let second = game.current_block().clone();
+        game.hard_drop();
+        assert_ne!(*game.phase(), Phase::Falling);
+
+        // Back to the second block, with the first one still locked
+        assert!(game.undo());
+        assert_eq!(game.current_block().config().collect::<Vec<_>>(), second.config().collect::<Vec<_>>());
+        assert_eq!(game.state_config().count(), 1);
+        assert!(game.undo());
+        assert_eq!(game.current_block().config().collect::<Vec<_>>(), first.config().collect::<Vec<_>>());
+        assert_eq!(game.state_config().count(), 0);
+        assert!(!game.undo());
+    }
+    #[test]
+    fn sandbox_edits_can_be_undone() {
+        let mut game = Tetris::new(10, 20, 1);
+        game.set_undo_limit(Some(10));
+        let cells = |game: &Tetris| game.state_config().flat_map(Block::config).copied().collect::<Vec<_>>();
+        game.record_edit();
+        game.set_cell(Coord(1, 19), Some(BlockID::I));
+        game.record_edit();
+        game.set_cell(Coord(2, 19), Some(BlockID::O));
+        game.record_edit();
+        game.set_cell(Coord(1, 19), None);
+        assert_eq!(cells(&game), [Coord(2, 19)]);
+
+        assert!(game.undo());
+        assert_eq!(cells(&game).len(), 2);
+        assert!(game.undo());
+        assert_eq!(cells(&game), [Coord(1, 19)]);
+        assert!(game.redo());
+        assert_eq!(cells(&game).len(), 2);
+    }
+    #[test]
+    fn line_clear_and_entry_delays_pass_in_order() {
+        let mut game = Tetris::new(4, 20, 1);
+        game.set_delays(Delays { entry_ms: 100, line_clear_ms: 300 });
+        game.spawn_current_block(BlockID::I);
+        game.hard_drop();
+        assert_eq!(*game.phase(), Phase::LineClear { rows: vec![19], remaining_ms: 300 });
+        assert_eq!(game.state_config().count(), 1);
+
+        assert!(!game.update(299));
+        assert!(!game.update(1));
+        assert_eq!(*game.phase(), Phase::Entry { remaining_ms: 100 });
+        assert_eq!(game.state_config().count(), 0);
+        assert!(!game.update(99));
+        assert!(game.update(1));
+        assert_eq!(*game.phase(), Phase::Falling);
+    }
+
+    #[test]
+    fn zero_delays_spawn_on_the_same_tick() {
+        let mut game = Tetris::new(4, 20, 1);
+        game.set_delays(Delays { entry_ms: 0, line_clear_ms: 0 });
+        game.spawn_current_block(BlockID::I);
+        game.hard_drop();
+        assert_eq!(*game.phase(), Phase::Falling);
+        assert_eq!(game.state_config().count(), 0);
+    }
+    // A game dealt only O blocks, which spawn on the top two rows
+    fn o_game() -> Tetris {
+        let o = PieceSet::tetrominoes().pieces[BlockID::O.index()].clone();
+        Tetris::with_pieces(10, 20, 1, PieceSet { name: String::from("O"), pieces: vec![o] })
+    }
+
+    fn top_outs(game: &mut Tetris) -> Vec<TopOut> {
+        game.drain_events().into_iter()
+            .filter_map(|event| match event {
+                GameEvent::TopOut { kind } => Some(kind),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_out_when_the_next_block_overlaps() {
+        let mut game = o_game();
+        // Full rows but for the last column, below the two rows the block spawns in
+        for y in 2..20 {
+            for x in 1..10 {
+                game.set_cell(Coord(x, y), Some(BlockID::G));
+            }
+        }
+        game.hard_drop();
+        assert_eq!(top_outs(&mut game), [TopOut::BlockOut]);
+        assert_eq!(game.top_out(), Some(TopOut::BlockOut));
+        assert!(game.status() == GameStatus::GameOver);
+    }
+
+    #[test]
+    fn lock_out_when_the_block_locks_above_the_board() {
+        let mut game = o_game();
+        let block = game.current_block().clone();
+        block.config().for_each(|&coord| game.set_cell(coord, Some(BlockID::G)));
+        game.set_current_block(&block + Coord(0, -2));
+        game.hard_drop();
+        assert_eq!(top_outs(&mut game), [TopOut::LockOut]);
+        assert_eq!(game.top_out(), Some(TopOut::LockOut));
+        assert!(game.status() == GameStatus::GameOver);
+    }
+    #[test]
+    fn rotation_and_hold_in_the_entry_delay_apply_on_spawn() {
+        let mut game = Tetris::new(10, 20, 1);
+        game.set_delays(Delays { entry_ms: 100, line_clear_ms: 0 });
+        game.hard_drop();
+        let next: Vec<BlockID> = game.peek_queue(2).iter().map(|block| *block.id()).collect();
+        for cmd in [GameCmd::Hold, GameCmd::Rotate(RotateCmd::Right), GameCmd::Shift(ShiftCmd::Left)] {
+            game.execute(&cmd);
+        }
+        assert_eq!(game.initial_input(), InitialInput { rotate: Some(RotateCmd::Right), hold: true });
+        game.drain_events();
+
+        assert!(game.update(100));
+        assert_eq!(game.held_block().map(|block| *block.id()), Some(next[0]));
+        assert_eq!(*game.current_block().id(), next[1]);
+        assert_eq!(game.current_block().orientation(), Orientation::Right);
+        assert_eq!(game.initial_input(), InitialInput::default());
+        let names: Vec<&str> = game.drain_events().iter().map(GameEvent::name).collect();
+        assert_eq!(names, ["Spawned", "Held", "Spawned", "Rotated"]);
+    }
+
+    #[test]
+    fn nes_rules_drop_input_in_the_entry_delay() {
+        let mut game = Tetris::new(10, 20, 1);
+        game.set_rules(Ruleset::Nes);
+        game.set_delays(Delays { entry_ms: 100, line_clear_ms: 0 });
+        game.hard_drop();
+        game.execute(&GameCmd::Rotate(RotateCmd::Right));
+        assert_eq!(game.initial_input(), InitialInput::default());
+        game.update(100);
+        assert_eq!(game.current_block().orientation(), Orientation::Spawn);
     }
 }
+
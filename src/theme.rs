@@ -0,0 +1,309 @@
+use std::fmt;
+
+use egui::Color32;
+use serde::{Serialize, Deserialize};
+
+use super::pieces::PieceSet;
+use super::enums::BlockID;
+
+// Tile skins that are built into the binary, so they also work on the web
+const SKINS: [(&str, &[u8]); 1] = [
+    ("bevel.png", include_bytes!("../assets/skins/bevel.png")),
+];
+
+// Folders searched for additional themes and skins on native
+#[cfg(not(target_arch = "wasm32"))]
+const THEME_DIR: &str = "assets/themes";
+#[cfg(not(target_arch = "wasm32"))]
+const SKIN_DIR: &str = "assets/skins";
+
+/// Colors and shapes used to paint the game, loadable from JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Theme {
+    pub name: String,
+    // Colors of I, J, L, O, S, T, Z and garbage (`BlockID` order), other pieces keep the color of their set
+    pub blocks: [Color32; 8],
+    pub wall: Color32,
+    pub background: Color32,
+    pub stroke: Color32,
+    pub stroke_width: f32,
+    pub rounding: f32,
+    // Grayscale tile image (e.g. `bevel.png`) from `assets/skins/`, tinted with the block color
+    #[serde(default)]
+    pub skin: Option<String>,
+}
+
+impl Theme {
+    /// Colors from the Tetris Guideline
+    pub fn guideline() -> Self {
+        Self {
+            name: String::from("Guideline"),
+            blocks: [
+                Color32::from_rgb(0, 240, 240),
+                Color32::from_rgb(0, 0, 240),
+                Color32::from_rgb(240, 160, 0),
+                Color32::from_rgb(240, 240, 0),
+                Color32::from_rgb(0, 240, 0),
+                Color32::from_rgb(160, 0, 240),
+                Color32::from_rgb(240, 0, 0),
+                Color32::from_rgb(120, 120, 120),
+            ],
+            wall: Color32::from_gray(200),
+            background: Color32::from_gray(27),
+            stroke: Color32::BLACK,
+            stroke_width: 2.0,
+            rounding: 2.0,
+            skin: None,
+        }
+    }
+
+    /// The pastel colors of the first versions of this game
+    pub fn classic() -> Self {
+        Self {
+            name: String::from("Classic"),
+            blocks: [
+                Color32::from_rgb(200, 150, 150),
+                Color32::from_rgb(150, 200, 150),
+                Color32::from_rgb(150, 150, 200),
+                Color32::from_rgb(200, 200, 150),
+                Color32::from_rgb(200, 150, 200),
+                Color32::from_rgb(150, 200, 200),
+                Color32::from_rgb(200, 200, 200),
+                Color32::from_rgb(120, 120, 120),
+            ],
+            wall: Color32::WHITE,
+            background: Color32::from_gray(27),
+            stroke: Color32::BLACK,
+            stroke_width: 2.0,
+            rounding: 2.0,
+            skin: None,
+        }
+    }
+
+    /// Saturated colors with bright outlines on black
+    pub fn high_contrast() -> Self {
+        Self {
+            name: String::from("High contrast"),
+            blocks: [
+                Color32::from_rgb(0, 255, 255),
+                Color32::from_rgb(40, 80, 255),
+                Color32::from_rgb(255, 140, 0),
+                Color32::from_rgb(255, 255, 0),
+                Color32::from_rgb(0, 255, 0),
+                Color32::from_rgb(220, 0, 255),
+                Color32::from_rgb(255, 0, 0),
+                Color32::from_gray(160),
+            ],
+            wall: Color32::WHITE,
+            background: Color32::BLACK,
+            stroke: Color32::WHITE,
+            stroke_width: 3.0,
+            rounding: 0.0,
+            skin: None,
+        }
+    }
+
+    /// Okabe-Ito colors, which stay apart for red-green (deuteranopia) color blindness
+    pub fn deuteranopia() -> Self {
+        Self::color_blind("Deuteranopia", [
+            Color32::from_rgb(86, 180, 233),
+            Color32::from_rgb(0, 114, 178),
+            Color32::from_rgb(230, 159, 0),
+            Color32::from_rgb(240, 228, 66),
+            Color32::from_rgb(0, 158, 115),
+            Color32::from_rgb(204, 121, 167),
+            Color32::from_rgb(213, 94, 0),
+            Color32::from_gray(110),
+        ])
+    }
+
+    /// Like deuteranopia, with brighter reds since red looks dark without red cones (protanopia)
+    pub fn protanopia() -> Self {
+        Self::color_blind("Protanopia", [
+            Color32::from_rgb(86, 180, 233),
+            Color32::from_rgb(0, 80, 200),
+            Color32::from_rgb(230, 159, 0),
+            Color32::from_rgb(240, 228, 66),
+            Color32::from_rgb(0, 158, 115),
+            Color32::from_rgb(190, 150, 230),
+            Color32::from_rgb(255, 120, 60),
+            Color32::from_gray(110),
+        ])
+    }
+
+    /// Reds, teals and grays, which stay apart for blue-yellow (tritanopia) color blindness
+    pub fn tritanopia() -> Self {
+        Self::color_blind("Tritanopia", [
+            Color32::from_rgb(0, 200, 200),
+            Color32::from_rgb(0, 90, 110),
+            Color32::from_rgb(255, 150, 150),
+            Color32::from_rgb(240, 240, 240),
+            Color32::from_rgb(60, 150, 140),
+            Color32::from_rgb(160, 30, 90),
+            Color32::from_rgb(220, 20, 30),
+            Color32::from_gray(110),
+        ])
+    }
+
+    // Color blind palettes share the high contrast board so the cells keep clear outlines
+    fn color_blind(name: &str, blocks: [Color32; 8]) -> Self {
+        Self { name: String::from(name), blocks, ..Self::high_contrast() }
+    }
+
+    /// The built-in themes
+    pub fn presets() -> Vec<Theme> {
+        vec![
+            Self::guideline(),
+            Self::classic(),
+            Self::high_contrast(),
+            Self::deuteranopia(),
+            Self::protanopia(),
+            Self::tritanopia(),
+        ]
+    }
+
+    /// The built-in themes followed by the ones found in `assets/themes/` (native only)
+    pub fn available() -> Vec<Theme> {
+        let mut themes = Self::presets();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(entries) = std::fs::read_dir(THEME_DIR) {
+            let mut paths: Vec<_> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect();
+            paths.sort();
+            themes.extend(paths.iter()
+                .filter_map(|path| std::fs::read_to_string(path).ok())
+                .filter_map(|json| Self::from_json(&json).ok()));
+        }
+        themes
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// Color of a piece of the set (or garbage cell)
+    pub fn color(&self, pieces: &PieceSet, id: &BlockID) -> Color32 {
+        match pieces.get(*id) {
+            Some(piece) => piece.tetromino().map_or(piece.color, |tetromino| self.blocks[tetromino.index()]),
+            None => self.blocks[self.blocks.len() - 1],
+        }
+    }
+
+    pub fn stroke(&self) -> egui::Stroke {
+        egui::Stroke::new(self.stroke_width, self.stroke)
+    }
+
+    pub fn rounding(&self) -> egui::Rounding {
+        egui::Rounding::same(self.rounding)
+    }
+
+    /// Decodes the skin of the theme, if it has one
+    pub fn load_skin(&self) -> Result<Option<egui::ColorImage>, SkinError> {
+        let Some(name) = &self.skin else { return Ok(None) };
+        let bytes = match SKINS.iter().find(|(skin, _)| skin == name) {
+            Some((_, bytes)) => bytes.to_vec(),
+            None => read_skin(name)?,
+        };
+        decode_png(&bytes).map(Some)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::guideline()
+    }
+}
+
+#[derive(Debug)]
+pub enum SkinError {
+    NotFound(String),
+    Decode(String),
+}
+
+impl fmt::Display for SkinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkinError::NotFound(name) => write!(f, "Skin {} not found", name),
+            SkinError::Decode(e) => write!(f, "Invalid skin: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SkinError {}
+
+// ------------------------------------------------------------------------------------------------
+// Aux functions
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_skin(name: &str) -> Result<Vec<u8>, SkinError> {
+    std::fs::read(std::path::Path::new(SKIN_DIR).join(name)).map_err(|_| SkinError::NotFound(name.to_string()))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_skin(name: &str) -> Result<Vec<u8>, SkinError> {
+    Err(SkinError::NotFound(name.to_string()))
+}
+
+fn decode_png(bytes: &[u8]) -> Result<egui::ColorImage, SkinError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| SkinError::Decode(e.to_string()))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| SkinError::Decode(e.to_string()))?;
+    let pixels = &buffer[..info.buffer_size()];
+
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => return Err(SkinError::Decode(String::from("unexpanded palette"))),
+    };
+    Ok(egui::ColorImage::from_rgba_unmultiplied([info.width as usize, info.height as usize], &rgba))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_round_trip_through_json() {
+        for theme in Theme::presets() {
+            assert_eq!(Theme::from_json(&theme.to_json()).unwrap(), theme);
+        }
+    }
+
+    #[test]
+    fn only_tetrominoes_take_the_theme_colors() {
+        let theme = Theme::classic();
+        let pieces = PieceSet::find("Tromino+Tetromino").unwrap();
+        assert_eq!(theme.color(&pieces, &BlockID::T), theme.blocks[BlockID::T.index()]);
+        assert_eq!(theme.color(&pieces, &BlockID(7)), pieces.pieces[7].color);
+        assert_eq!(theme.color(&pieces, &BlockID::G), theme.blocks[7]);
+    }
+
+    #[test]
+    fn built_in_skins_decode() {
+        let theme = Theme { skin: Some(String::from("bevel.png")), ..Theme::guideline() };
+        assert!(theme.load_skin().unwrap().is_some());
+        let theme = Theme { skin: Some(String::from("missing.png")), ..Theme::guideline() };
+        assert!(matches!(theme.load_skin(), Err(SkinError::NotFound(_))));
+    }
+    #[test]
+    fn color_blind_palettes_tell_every_block_apart() {
+        for theme in [Theme::deuteranopia(), Theme::protanopia(), Theme::tritanopia()] {
+            for (i, color) in theme.blocks.iter().enumerate() {
+                assert!(!theme.blocks[..i].contains(color), "{}: {:?}", theme.name, color);
+            }
+            assert_eq!(theme.stroke(), Theme::high_contrast().stroke());
+        }
+    }
+}
+
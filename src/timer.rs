@@ -1,16 +1,36 @@
-// Chrono is the WASM safe timing library if used with features ["js-sys", "wasmbind"]
-use chrono;
+use serde::{Serialize, Deserialize};
 
+use super::enums::Ruleset;
+
+// Gravity of a new game (in milliseconds, ms), and how it speeds up with the score
+pub const START_PERIOD: i64 = 1024;
+pub const MIN_PERIOD: i64 = 32;
+pub const LVL_UP: i64 = 500;
+
+// Logical frames per second of the simulation, and how much real time a slow frame may make up for
+pub const FRAME_RATE: i64 = 60;
+pub const MAX_CATCH_UP_MS: i64 = 250;
+
+// Frames the NES takes per row of gravity by level, from level 29 on a row every frame
+const NES_FRAMES_PER_ROW: [i64; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6,
+    5, 5, 5, 4, 4, 4, 3, 3, 3, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 1,
+];
+
+#[derive(Clone, Serialize, Deserialize)]
 struct TickPeriod {
     min_period: i64,
-    period: i64,  // Update frequency in milliseconds (ms)
+    period: i64,  // Update frequency in milliseconds (ms), in frames under NES rules
     next_lvl: i64,
     lvl_up: i64,
+    // NES rules take the period from the speed table instead of halving it with the score
+    rules: Ruleset,
 }
 
 impl TickPeriod {
     fn new(period: i64, min_period: i64, lvl_up: i64) -> Self {
-        Self { period, min_period, lvl_up, next_lvl: lvl_up }
+        Self { period, min_period, lvl_up, next_lvl: lvl_up, rules: Ruleset::Modern }
     }
 
     fn decrease_period(&mut self) {
@@ -20,41 +40,208 @@ impl TickPeriod {
     }
 
     fn update_period_from_score(&mut self, score: usize) {
-        if score as i64 >= self.next_lvl {
+        if self.rules == Ruleset::Modern && score as i64 >= self.next_lvl {
             self.next_lvl += self.lvl_up;
             self.decrease_period();
         }
     }
 
+    fn set_nes_level(&mut self, level: usize) {
+        self.period = NES_FRAMES_PER_ROW[level.min(NES_FRAMES_PER_ROW.len() - 1)];
+    }
+
     fn get_period(&self) -> i64 {
         self.period
     }
 }
 
+/// Counts the time of the game, the gravity ticks whenever a whole period has passed.
+/// The time left over carries to the next tick, so ticks do not drift however the time is fed.
+/// NES gravity counts the frames of the frame clock instead, like the NES does
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TickTimer {
     period: TickPeriod,
-    time: i64,
+    // Game time (or frames) since the last tick
+    elapsed: i64,
 }
 
 impl TickTimer {
     pub fn new(period: i64, min_period: i64, lvl_up: i64) -> Self {
-        let time = chrono::Local::now().timestamp_millis();
         Self {
             period: TickPeriod::new(period, min_period, lvl_up),
-            time
+            elapsed: 0,
         }
     }
 
+    /// The gravity of a new game under `rules` that starts at `level`
+    pub fn with_rules(rules: Ruleset, level: usize) -> Self {
+        let mut timer = Self::new(START_PERIOD, MIN_PERIOD, LVL_UP);
+        timer.period.rules = rules;
+        timer.set_level(level);
+        timer
+    }
+
     pub fn update_period_from_score(&mut self, score: usize) {
         self.period.update_period_from_score(score);
     }
 
+    /// Speeds the gravity up for the score, or for the level under NES rules
+    pub fn update_period(&mut self, score: usize, level: usize) {
+        match self.period.rules {
+            Ruleset::Modern => self.period.update_period_from_score(score),
+            Ruleset::Nes => self.period.set_nes_level(level),
+        }
+    }
+
+    /// Speeds the gravity up as if `level` (starting at 1, at 0 under NES rules) had been reached
+    pub fn set_level(&mut self, level: usize) {
+        match self.period.rules {
+            Ruleset::Modern => for _ in 1..level {
+                self.period.decrease_period();
+            },
+            Ruleset::Nes => self.period.set_nes_level(level),
+        }
+    }
+
+    /// Lets `elapsed_ms` of game time pass
+    pub fn advance(&mut self, elapsed_ms: i64) {
+        self.elapsed += elapsed_ms;
+    }
+
+    /// Lets one frame of the frame clock pass, which lasted `elapsed_ms`
+    pub fn advance_frame(&mut self, elapsed_ms: i64) {
+        self.elapsed += match self.period.rules {
+            Ruleset::Modern => elapsed_ms,
+            Ruleset::Nes => 1,
+        };
+    }
+
+    /// Takes one tick if it is due, call it until it returns false to catch up
+    pub fn take_tick(&mut self) -> bool {
+        let period = self.period.get_period();
+        if self.elapsed < period {
+            return false
+        }
+        self.elapsed -= period;
+        true
+    }
+
+    /// Game time until the next tick (in milliseconds, ms)
     pub fn get_time_until_tick(&self) -> i64 {
-        (self.time + self.period.get_period()) - chrono::Local::now().timestamp_millis()
+        let left = self.period.get_period() - self.elapsed;
+        match self.period.rules {
+            Ruleset::Modern => left,
+            Ruleset::Nes => left * 1000 / FRAME_RATE,
+        }
     }
 
     pub fn reset_tick(&mut self) {
-        self.time = chrono::Local::now().timestamp_millis();
+        self.elapsed = 0;
+    }
+}
+
+/// Turns real time into logical frames of fixed length, the game steps once per frame
+/// no matter how often (or how late) the screen is drawn
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    frame_rate: i64,
+    frames: i64,
+    // Real time not simulated yet, in microseconds times the frame rate so a frame is a whole number
+    pending: i64,
+}
+
+impl FrameClock {
+    pub fn new(frame_rate: i64) -> Self {
+        Self { frame_rate: frame_rate.max(1), frames: 0, pending: 0 }
+    }
+
+    /// Adds `elapsed_us` of real time, returns how many frames are due.
+    /// Anything beyond `MAX_CATCH_UP_MS` (e.g., a stall or a hidden tab) is dropped
+    pub fn advance(&mut self, elapsed_us: i64) -> usize {
+        let max_pending = MAX_CATCH_UP_MS * 1000 * self.frame_rate;
+        self.pending = (self.pending + elapsed_us.max(0) * self.frame_rate).min(max_pending);
+        (self.pending / 1_000_000) as usize
+    }
+
+    /// Steps one frame, returns how long it lasted (in milliseconds, which add up without drifting)
+    pub fn step(&mut self) -> i64 {
+        let start = self.time_ms();
+        self.pending = (self.pending - 1_000_000).max(0);
+        self.frames += 1;
+        self.time_ms() - start
+    }
+
+    /// Frames stepped since the clock started
+    pub fn frames(&self) -> i64 {
+        self.frames
+    }
+
+    /// Simulated time since the clock started
+    pub fn time_ms(&self) -> i64 {
+        self.frames * 1000 / self.frame_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nes_gravity_counts_whole_frames() {
+        let mut clock = FrameClock::new(FRAME_RATE);
+        let mut timer = TickTimer::with_rules(Ruleset::Nes, 19);
+        let mut ticks = 0;
+        for _ in 0..clock.advance(10_000_000) {
+            timer.advance_frame(clock.step());
+            while timer.take_tick() {
+                ticks += 1;
+            }
+        }
+        // The frames of a quarter second (the catch up limit), 2 frames per row at level 19
+        assert_eq!(ticks, (MAX_CATCH_UP_MS * FRAME_RATE / 1000) / 2);
+
+        let mut timer = TickTimer::with_rules(Ruleset::Nes, 0);
+        for _ in 0..47 {
+            timer.advance_frame(17);
+            assert!(!timer.take_tick());
+        }
+        timer.advance_frame(16);
+        assert!(timer.take_tick());
+    }
+    #[test]
+    fn frames_add_up_without_drift() {
+        let mut clock = FrameClock::new(FRAME_RATE);
+        let mut time_ms = 0;
+        // A 144 Hz screen, which draws two or three times per frame
+        for _ in 0..144 {
+            for _ in 0..clock.advance(1_000_000 / 144) {
+                time_ms += clock.step();
+            }
+        }
+        assert!((FRAME_RATE - 1..=FRAME_RATE).contains(&clock.frames()), "{}", clock.frames());
+        assert_eq!(time_ms, clock.time_ms());
+        assert_eq!(FrameClock::new(FRAME_RATE).advance(1_000_000 / FRAME_RATE - 1), 0);
+    }
+
+    #[test]
+    fn stalls_are_not_caught_up() {
+        let mut clock = FrameClock::new(FRAME_RATE);
+        let max_frames = (MAX_CATCH_UP_MS * FRAME_RATE / 1000) as usize;
+        assert_eq!(clock.advance(5_000_000), max_frames);
+        // Time going backwards is ignored
+        assert_eq!(clock.advance(-1_000), max_frames);
+    }
+
+    #[test]
+    fn gravity_keeps_the_time_left_over() {
+        let mut timer = TickTimer::new(100, MIN_PERIOD, LVL_UP);
+        timer.advance(250);
+        assert!(timer.take_tick());
+        assert!(timer.take_tick());
+        assert!(!timer.take_tick());
+        assert_eq!(timer.get_time_until_tick(), 50);
+        timer.advance(50);
+        assert!(timer.take_tick());
     }
 }
 
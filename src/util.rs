@@ -1,6 +1,8 @@
 use std::ops::{Add, Mul};
 
-#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Copy, Clone, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Coord(pub i32, pub i32);
 
 impl Add for Coord {
@@ -26,3 +28,28 @@ impl Mul<Coord> for f32 {
         Coord((rhs.0 as f32 * self) as i32, (rhs.1 as f32 * self) as i32)
     }
 }
+
+/// Small seedable pseudo random number generator (SplitMix64), so a game can be replayed from its seed
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed number in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}